@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result as AnyResult;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::{serialization::FullFicInfo, sources::SpineSearch};
+
+/// What is known about an epub file when its metadata gets cached.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileFingerprint {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub content_hash: Option<String>,
+}
+
+impl FileFingerprint {
+    /// Fingerprint without the content hash, which is only computed when needed.
+    pub fn of<P: AsRef<Path>>(path: P) -> AnyResult<Self> {
+        let metadata = std::fs::metadata(&path)?;
+        Ok(FileFingerprint {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            content_hash: None,
+        })
+    }
+}
+
+fn hash_file_contents<P: AsRef<Path>>(path: P) -> AnyResult<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    fingerprint: FileFingerprint,
+    fic_info: FullFicInfo,
+    /// Spine documents that were searched for the tags
+    search: SpineSearch,
+}
+
+impl CacheEntry {
+    /// Whether the cached info stands for a scan with `search`: tags that were found stay
+    /// found, but a wider search may find tags that were missed.
+    fn is_valid_for(&self, search: SpineSearch) -> bool {
+        self.fic_info.tags.is_ok() || self.search.covers(search)
+    }
+}
+
+/// Version of the cache format. Bump it whenever the parsed shape of [`FullFicInfo`] or the
+/// way epubs are parsed changes, so that the entries of older versions are parsed again
+/// instead of being served with their missing fields left empty.
+const CACHE_VERSION: u32 = 4;
+
+/// Results of previous scans, keyed by path, so that unchanged epubs are not parsed again.
+///
+/// A file is considered unchanged when its size and modification time match the cached
+/// ones or, if content hashing is enabled, when its size and SHA-256 hash do. Epubs whose
+/// tags were not found are parsed again when the spine is searched further than before.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ScanCache {
+    version: u32,
+    entries: HashMap<PathBuf, CacheEntry>,
+    #[serde(skip)]
+    use_content_hash: bool,
+}
+
+impl Default for ScanCache {
+    fn default() -> Self {
+        ScanCache {
+            version: CACHE_VERSION,
+            entries: HashMap::new(),
+            use_content_hash: false,
+        }
+    }
+}
+
+/// Start of a cache file, read before the entries to check the version.
+#[derive(serde::Deserialize)]
+struct CacheHeader {
+    version: u32,
+}
+
+impl ScanCache {
+    /// Loads the cache stored at `path`, starting from an empty one if it is missing, cannot
+    /// be read or was written by another version.
+    pub fn load<P: AsRef<Path>>(path: P, use_content_hash: bool) -> Self {
+        let path = path.as_ref();
+        let mut cache = if path.exists() {
+            Self::read(path).unwrap_or_else(|err| {
+                warn!(
+                    "ignoring unreadable cache `{}`: {}",
+                    path.to_string_lossy(),
+                    err
+                );
+                Self::default()
+            })
+        } else {
+            Self::default()
+        };
+        cache.use_content_hash = use_content_hash;
+        cache
+    }
+
+    fn read(path: &Path) -> AnyResult<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let header: CacheHeader = serde_json::from_str(&content)?;
+        if header.version != CACHE_VERSION {
+            info!(
+                "discarding cache `{}` written by another version",
+                path.to_string_lossy()
+            );
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Writes the cache to `path`, dropping entries for files that no longer exist.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> AnyResult<()> {
+        self.entries.retain(|fic_path, _| fic_path.exists());
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Returns the current fingerprint of the file at `path` and, if it is unchanged and was
+    /// scanned with a search covering `search`, its cached info with the search it was
+    /// scanned with.
+    pub fn lookup(
+        &self,
+        path: &Path,
+        search: SpineSearch,
+    ) -> AnyResult<(FileFingerprint, Option<(FullFicInfo, SpineSearch)>)> {
+        let mut fingerprint = FileFingerprint::of(path)?;
+        let entry = self.entries.get(path);
+        let Some(entry) = entry.filter(|entry| entry.is_valid_for(search)) else {
+            if self.use_content_hash {
+                fingerprint.content_hash = Some(hash_file_contents(path)?);
+            }
+            return Ok((fingerprint, None));
+        };
+
+        let cached = &entry.fingerprint;
+        let hit = Some((entry.fic_info.clone(), entry.search));
+        if cached.size == fingerprint.size && cached.modified == fingerprint.modified {
+            fingerprint.content_hash = cached.content_hash.clone();
+            return Ok((fingerprint, hit));
+        }
+        if self.use_content_hash {
+            fingerprint.content_hash = Some(hash_file_contents(path)?);
+            if cached.size == fingerprint.size && cached.content_hash == fingerprint.content_hash {
+                info!("`{}` was touched but not modified", path.to_string_lossy());
+                return Ok((fingerprint, hit));
+            }
+        }
+        Ok((fingerprint, None))
+    }
+
+    pub fn insert(
+        &mut self,
+        fingerprint: FileFingerprint,
+        fic_info: FullFicInfo,
+        search: SpineSearch,
+    ) {
+        self.entries.insert(
+            fic_info.meta_info.path_to_file.clone(),
+            CacheEntry {
+                fingerprint,
+                fic_info,
+                search,
+            },
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use anyhow::Result as AnyResult;
+use rusqlite::{params, Connection, Transaction};
+
+use crate::{serialization::FullFicInfo, utils::canonical_path};
+
+const SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+
+CREATE TABLE IF NOT EXISTS works (
+    id          INTEGER PRIMARY KEY,
+    path        TEXT NOT NULL UNIQUE,
+    title       TEXT,
+    description TEXT,
+    rating      TEXT,
+    language    TEXT,
+    stats       TEXT,
+    tags_error  TEXT,
+    error_kind  TEXT,
+    -- the whole `FullFicInfo`, used to regenerate exports without rescanning
+    info_json   TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS creators (
+    id   INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS work_creators (
+    work_id    INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE,
+    creator_id INTEGER NOT NULL REFERENCES creators (id),
+    PRIMARY KEY (work_id, creator_id)
+);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id   INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (kind, name)
+);
+
+CREATE TABLE IF NOT EXISTS work_tags (
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE,
+    tag_id  INTEGER NOT NULL REFERENCES tags (id),
+    PRIMARY KEY (work_id, tag_id)
+);
+
+CREATE TABLE IF NOT EXISTS work_series (
+    work_id INTEGER NOT NULL REFERENCES works (id) ON DELETE CASCADE,
+    name    TEXT NOT NULL,
+    part    INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS work_tags_by_tag ON work_tags (tag_id);
+CREATE INDEX IF NOT EXISTS work_creators_by_creator ON work_creators (creator_id);
+";
+
+/// SQLite database holding the results of previous scans.
+///
+/// Works are keyed by the canonical path of their epub, so that the catalog can be updated
+/// from any directory; creators and tags are normalized into their own tables and linked to
+/// works through `work_creators` and `work_tags`.
+pub struct Catalog {
+    conn: Connection,
+}
+
+impl Catalog {
+    pub fn open<P: AsRef<Path>>(path: P) -> AnyResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Catalog { conn })
+    }
+
+    /// Inserts `fics`, replacing previously stored works with the same path.
+    pub fn store_fics<'a>(
+        &mut self,
+        fics: impl IntoIterator<Item = &'a FullFicInfo>,
+    ) -> AnyResult<()> {
+        let tx = self.conn.transaction()?;
+        for fic_info in fics {
+            store_fic(&tx, fic_info)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes the works stored for the epubs at `paths`.
+    pub fn remove_fics(&mut self, paths: &[&Path]) -> AnyResult<usize> {
+        let tx = self.conn.transaction()?;
+        let mut n_removed = 0;
+        for path in paths {
+            n_removed += tx.execute(
+                "DELETE FROM works WHERE path = ?1",
+                [canonical_path(path).to_string_lossy()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(n_removed)
+    }
+
+    /// Removes the works whose epub no longer exists on disk, returning how many were removed.
+    pub fn prune_missing_files(&mut self) -> AnyResult<usize> {
+        let tx = self.conn.transaction()?;
+        let missing_paths = {
+            let mut stmt = tx.prepare("SELECT path FROM works")?;
+            let paths = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            paths
+                .into_iter()
+                .filter(|path| !Path::new(path).exists())
+                .collect::<Vec<_>>()
+        };
+        for path in &missing_paths {
+            tx.execute("DELETE FROM works WHERE path = ?1", [path])?;
+        }
+        tx.commit()?;
+        Ok(missing_paths.len())
+    }
+
+    /// Loads every stored work, in the order they were first inserted.
+    pub fn load_fics(&self) -> AnyResult<Vec<FullFicInfo>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT info_json FROM works ORDER BY id")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|info_json| Ok(serde_json::from_str(&info_json?)?))
+            .collect()
+    }
+}
+
+fn store_fic(tx: &Transaction, fic_info: &FullFicInfo) -> AnyResult<()> {
+    let mut fic_info = fic_info.clone();
+    fic_info.meta_info.path_to_file = canonical_path(&fic_info.meta_info.path_to_file);
+    let meta_info = &fic_info.meta_info;
+    let path = meta_info.path_to_file.to_string_lossy();
+    let tags = fic_info.tags.as_ref().ok();
+
+    let work_id: i64 = tx.query_row(
+        "INSERT INTO works
+            (path, title, description, rating, language, stats, tags_error, error_kind, info_json)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+         ON CONFLICT (path) DO UPDATE SET
+            title = excluded.title,
+            description = excluded.description,
+            rating = excluded.rating,
+            language = excluded.language,
+            stats = excluded.stats,
+            tags_error = excluded.tags_error,
+            error_kind = excluded.error_kind,
+            info_json = excluded.info_json
+         RETURNING id",
+        params![
+            path,
+            meta_info.title,
+            meta_info.description,
+            tags.and_then(|tags| tags.rating.as_ref()),
+            tags.and_then(|tags| tags.language.as_ref()),
+            tags.and_then(|tags| tags.stats.as_ref()),
+            fic_info.tags.as_ref().err().map(|err| &err.message),
+            fic_info.tags.as_ref().err().map(|err| err.kind.name()),
+            serde_json::to_string(&fic_info)?,
+        ],
+        |row| row.get(0),
+    )?;
+
+    tx.execute("DELETE FROM work_creators WHERE work_id = ?1", [work_id])?;
+    for creator in &meta_info.creators {
+        tx.execute(
+            "INSERT OR IGNORE INTO creators (name) VALUES (?1)",
+            [creator],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO work_creators (work_id, creator_id)
+             SELECT ?1, id FROM creators WHERE name = ?2",
+            params![work_id, creator],
+        )?;
+    }
+
+    tx.execute("DELETE FROM work_series WHERE work_id = ?1", [work_id])?;
+    for series in tags.map(|tags| &tags.series[..]).unwrap_or_default() {
+        tx.execute(
+            "INSERT INTO work_series (work_id, name, part) VALUES (?1, ?2, ?3)",
+            params![work_id, series.name, series.part],
+        )?;
+    }
+
+    tx.execute("DELETE FROM work_tags WHERE work_id = ?1", [work_id])?;
+    for (kind, names) in tags.map(|tags| tags.tag_lists()).into_iter().flatten() {
+        for name in names {
+            tx.execute(
+                "INSERT OR IGNORE INTO tags (kind, name) VALUES (?1, ?2)",
+                [kind.name(), name],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO work_tags (work_id, tag_id)
+                 SELECT ?1, id FROM tags WHERE kind = ?2 AND name = ?3",
+                params![work_id, kind.name(), name],
+            )?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,312 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+};
+
+use log::warn;
+use rbook::Epub;
+use regex::Regex;
+use roxmltree::{Document, Node};
+use rust_xlsxwriter::{Format, Worksheet};
+
+use crate::{
+    serialization::FullFicInfo,
+    sources::{labelled_blocks, Spine},
+    utils::{mkregex, node_inner_text},
+};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChapterInfo {
+    /// Position of the chapter in the work, starting from 1
+    pub number: u32,
+    /// Label of the chapter in the table of contents
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    #[serde(flatten)]
+    pub length: TextLength,
+}
+
+/// Average reading speeds used for the reading time estimate.
+const WORDS_PER_MINUTE: u64 = 238;
+const CJK_CHARACTERS_PER_MINUTE: u64 = 350;
+
+/// Length of a text, counted like AO3 does: CJK scripts do not separate words with spaces,
+/// so each of their characters counts as a word.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TextLength {
+    pub words: u64,
+    /// Characters other than whitespace
+    pub characters: u64,
+    /// CJK characters, also included in `words`
+    #[serde(default)]
+    pub cjk_characters: u64,
+}
+
+impl TextLength {
+    pub fn of(text: &str) -> Self {
+        let mut length = Self::default();
+        let mut in_word = false;
+        for c in text.chars() {
+            if c.is_whitespace() {
+                in_word = false;
+                continue;
+            }
+            length.characters += 1;
+            if is_cjk(c) {
+                length.words += 1;
+                length.cjk_characters += 1;
+                in_word = false;
+            } else if !in_word {
+                length.words += 1;
+                in_word = true;
+            }
+        }
+        length
+    }
+
+    /// Estimated reading time, rounded up to the minute.
+    pub fn reading_minutes(&self) -> u64 {
+        let other_words = self.words - self.cjk_characters;
+        (other_words * CJK_CHARACTERS_PER_MINUTE + self.cjk_characters * WORDS_PER_MINUTE)
+            .div_ceil(WORDS_PER_MINUTE * CJK_CHARACTERS_PER_MINUTE)
+    }
+}
+
+impl std::ops::AddAssign for TextLength {
+    fn add_assign(&mut self, other: Self) {
+        self.words += other.words;
+        self.characters += other.characters;
+        self.cjk_characters += other.cjk_characters;
+    }
+}
+
+impl std::iter::Sum for TextLength {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |mut total, length| {
+            total += length;
+            total
+        })
+    }
+}
+
+/// Han ideographs, kana and their extensions. Hangul is left out: Korean separates words
+/// with spaces.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{31f0}'..='\u{31ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2ffff}'
+    )
+}
+
+mkregex!(
+    RE_FRONT_BACK_MATTER,
+    r"(?i)^\W*(preface|afterword|title page|cover|(table of )?contents|information|шапка|обложка|оглавление)\W*$"
+);
+
+/// Lists the chapters of the work by walking the spine, naming them after the table of
+/// contents. Preface, afterword and title pages are left out; documents with no entry in the
+/// table of contents continue the previous chapter (long chapters are often split).
+pub fn list_chapters(epub: &Epub, spine: &Spine) -> Vec<ChapterInfo> {
+    let opf_dir = epub.root_file_directory();
+    // Entries of the table of contents are relative to its own file.
+    let toc_dir = toc_path(epub)
+        .and_then(|toc_path| toc_path.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| opf_dir.to_path_buf());
+    let toc = epub
+        .toc()
+        .elements_flat()
+        .into_iter()
+        .map(|entry| (resolve_href(&toc_dir, entry.value()), entry.name()))
+        .collect::<Vec<_>>();
+    let manifest = epub.manifest();
+    let mut chapters: Vec<ChapterInfo> = vec![];
+    for (spine_item, document) in epub.spine().elements().into_iter().zip(&spine.documents) {
+        let toc_label = manifest
+            .by_id(spine_item.name())
+            .map(|item| resolve_href(opf_dir, item.value()))
+            .and_then(|path| toc.iter().find(|(toc_path, _)| *toc_path == path))
+            .map(|(_, label)| label.trim().to_string());
+        let doc = match &document.doc {
+            Ok(doc) => doc,
+            Err(err) => {
+                warn!("cannot read `{}` for chapter list: {}", document.path, err);
+                continue;
+            }
+        };
+        if is_front_or_back_matter(doc, toc_label.as_deref()) {
+            continue;
+        }
+
+        let length = chapter_text_length(doc);
+        match (toc_label, chapters.last_mut()) {
+            (None, _) if length.words == 0 => (),
+            (None, Some(previous)) => previous.length += length,
+            (title, _) => chapters.push(ChapterInfo {
+                number: (chapters.len() + 1).try_into().unwrap_or(u32::MAX),
+                title,
+                summary: labelled_blocks(doc)
+                    .into_iter()
+                    .find(|(label, _)| label == "chapter summary")
+                    .map(|(_, text)| text),
+                length,
+            }),
+        }
+    }
+    chapters
+}
+
+/// Path of the table of contents in the archive: the EPUB3 navigation document, or the NCX.
+fn toc_path(epub: &Epub) -> Option<PathBuf> {
+    let manifest = epub.manifest();
+    manifest
+        .by_property("nav")
+        .or_else(|| manifest.by_media_type("application/x-dtbncx+xml"))
+        .map(|item| resolve_href(epub.root_file_directory(), item.value()))
+}
+
+/// Path in the archive of `href` (possibly with a `#fragment`) relative to `base_dir`, with
+/// `.` and `..` resolved.
+fn resolve_href(base_dir: &Path, href: &str) -> PathBuf {
+    let href = href.split('#').next().unwrap_or("");
+    let mut path = PathBuf::new();
+    for component in base_dir.join(href).components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir => (),
+            component => path.push(component),
+        }
+    }
+    path
+}
+
+fn is_front_or_back_matter(doc: &Document, toc_label: Option<&str>) -> bool {
+    toc_label.is_some_and(|label| RE_FRONT_BACK_MATTER.is_match(label))
+        || doc
+            .descendants()
+            .any(|elt| matches!(elt.attribute("id"), Some("preface" | "afterword")))
+}
+
+/// Length of the chapter text. AO3 keeps the text in `<div class="userstuff">`, apart from
+/// the heading and notes; other epubs count the whole body.
+fn chapter_text_length(doc: &Document) -> TextLength {
+    let is_userstuff = |elt: &Node| {
+        elt.has_tag_name("div")
+            && elt
+                .attribute("class")
+                .is_some_and(|class| class.split_whitespace().any(|class| class == "userstuff"))
+    };
+    let mut text_roots = doc.descendants().filter(is_userstuff).collect::<Vec<_>>();
+    if text_roots.is_empty() {
+        text_roots.extend(doc.descendants().find(|elt| elt.has_tag_name("body")));
+    }
+    text_roots
+        .iter()
+        .map(|root| TextLength::of(&node_inner_text(root)))
+        .sum()
+}
+
+const CHAPTERS_COLUMNS: [&str; 7] = [
+    "path_to_file",
+    "work_title",
+    "number",
+    "title",
+    "summary",
+    "words",
+    "characters",
+];
+
+pub fn write_chapters_worksheet(
+    worksheet: &mut Worksheet,
+    fics: &[FullFicInfo],
+) -> anyhow::Result<()> {
+    worksheet.set_name("chapters")?;
+    worksheet.write_row_with_format(0, 0, CHAPTERS_COLUMNS, &Format::new().set_bold())?;
+
+    let mut row = 0;
+    for fic_info in fics {
+        for chapter in &fic_info.chapters {
+            row += 1;
+            worksheet.write_string(row, 0, fic_info.meta_info.path_to_file.to_string_lossy())?;
+            worksheet.write_string(row, 1, fic_info.meta_info.title.as_deref().unwrap_or(""))?;
+            worksheet.write_number(row, 2, chapter.number)?;
+            if let Some(title) = &chapter.title {
+                worksheet.write_string(row, 3, title)?;
+            }
+            if let Some(summary) = &chapter.summary {
+                worksheet.write_string(row, 4, summary)?;
+            }
+            worksheet.write_number(row, 5, chapter.length.words as f64)?;
+            worksheet.write_number(row, 6, chapter.length.characters as f64)?;
+        }
+    }
+    worksheet.autofit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_words_separated_by_whitespace() {
+        let length = TextLength::of("  The quick brown\nfox jumps.  ");
+        assert_eq!(length.words, 5);
+        assert_eq!(length.characters, 22);
+        assert_eq!(length.cjk_characters, 0);
+    }
+
+    #[test]
+    fn counts_each_cjk_character_as_a_word() {
+        let length = TextLength::of("今日はいい天気");
+        assert_eq!(length.words, 7);
+        assert_eq!(length.cjk_characters, 7);
+        assert_eq!(length.characters, 7);
+    }
+
+    #[test]
+    fn counts_mixed_scripts() {
+        // Latin words stop at CJK characters, Hangul words are separated by spaces.
+        let length = TextLength::of("Harry说你好 안녕하세요 친구");
+        assert_eq!(length.cjk_characters, 3);
+        assert_eq!(length.words, 1 + 3 + 2);
+    }
+
+    #[test]
+    fn reading_time_is_rounded_up() {
+        assert_eq!(TextLength::default().reading_minutes(), 0);
+        assert_eq!(TextLength::of("word").reading_minutes(), 1);
+        let length = TextLength {
+            words: WORDS_PER_MINUTE * 2 + CJK_CHARACTERS_PER_MINUTE,
+            characters: 0,
+            cjk_characters: CJK_CHARACTERS_PER_MINUTE,
+        };
+        assert_eq!(length.reading_minutes(), 3);
+    }
+
+    #[test]
+    fn resolves_hrefs_against_their_directory() {
+        assert_eq!(
+            resolve_href(Path::new("OEBPS/Nav"), "../Text/ch1.xhtml#start"),
+            PathBuf::from("OEBPS/Text/ch1.xhtml")
+        );
+        assert_eq!(
+            resolve_href(Path::new("OEBPS"), "./Text/ch1.xhtml"),
+            PathBuf::from("OEBPS/Text/ch1.xhtml")
+        );
+        // Same file name in another directory
+        assert_ne!(
+            resolve_href(Path::new("OEBPS"), "Notes/ch1.xhtml"),
+            resolve_href(Path::new("OEBPS"), "Text/ch1.xhtml")
+        );
+        assert_eq!(
+            resolve_href(Path::new(""), "ch1.xhtml"),
+            PathBuf::from("ch1.xhtml")
+        );
+    }
+}
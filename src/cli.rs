@@ -0,0 +1,262 @@
+use std::path::PathBuf;
+
+use anyhow::Result as AnyResult;
+use clap::{ArgAction, Args, Parser, Subcommand};
+use itertools::Itertools;
+use log::LevelFilter;
+
+use crate::{
+    cache::ScanCache,
+    catalog::Catalog,
+    duplicates::find_duplicates,
+    export::{export_catalog, ExportFormat, ExportOptions},
+    filter::FicFilter,
+    get_data::scan_library,
+    organize::{organize, undo_organize, OrganizeOptions},
+    serialization::FullFicInfo,
+    sources::SpineSearch,
+    watch::watch_library,
+    write_back::write_library_metadata,
+};
+
+/// Explore a library of fanfiction epubs and export their metadata.
+///
+/// Launches the graphical interface when no subcommand is given.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Print more log messages, can be repeated
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Print fewer log messages, can be repeated
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// Number of epubs to explore in parallel, defaults to the number of cores
+    #[arg(short, long, global = true)]
+    pub jobs: Option<usize>,
+}
+
+impl Cli {
+    pub fn log_level(&self) -> LevelFilter {
+        const LEVELS: [LevelFilter; 6] = [
+            LevelFilter::Off,
+            LevelFilter::Error,
+            LevelFilter::Warn,
+            LevelFilter::Info,
+            LevelFilter::Debug,
+            LevelFilter::Trace,
+        ];
+        let default = if cfg!(debug_assertions) {
+            LevelFilter::Info
+        } else {
+            LevelFilter::Warn
+        } as usize;
+        let level = (default + self.verbose as usize).saturating_sub(self.quiet as usize);
+        LEVELS[level.min(LEVELS.len() - 1)]
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List every epub found in the input directories along with its title
+    Scan(ScanArgs),
+    /// Export the metadata of every epub found in the input directories
+    Export(ExportArgs),
+    /// Export the catalog, then keep it up to date as epubs are added, changed or removed in
+    /// the input directories
+    Watch(ExportArgs),
+    /// Print summary statistics about the epubs found in the input directories
+    Stats(ScanArgs),
+    /// List works that were downloaded more than once, newest copy first
+    Duplicates(ScanArgs),
+    /// Move or copy the epubs into a directory layout built from their metadata
+    Organize(OrganizeArgs),
+    /// Revert an `organize` run using the undo log it wrote
+    UndoOrganize {
+        /// Undo log written by `organize`
+        log: PathBuf,
+    },
+    /// Write the parsed tags, series and language into the package document (OPF) of the
+    /// epubs
+    WriteMetadata(WriteMetadataArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ScanArgs {
+    /// Directories (or single epub files) to search for epubs
+    #[arg(required_unless_present = "from_catalog")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Read works from an SQLite catalog written by `export` instead of scanning
+    #[arg(long, conflicts_with = "inputs")]
+    pub from_catalog: Option<PathBuf>,
+
+    /// Cache file used to skip epubs that did not change since the previous scan
+    #[arg(long, conflicts_with = "from_catalog")]
+    pub cache: Option<PathBuf>,
+
+    /// Also compare content hashes, so that touched but unmodified epubs stay cached
+    #[arg(long, requires = "cache")]
+    pub hash: bool,
+
+    /// Number of spine documents searched for the tags, 5 by default
+    #[arg(long, conflicts_with = "whole_spine")]
+    pub search_depth: Option<usize>,
+
+    /// Search the whole spine for the tags
+    #[arg(long)]
+    pub whole_spine: bool,
+
+    /// Only keep the works matching this expression, e.g.
+    /// `rating:Explicit AND fandom:"Harry Potter" AND NOT warning:"Major Character Death" AND words>50000`.
+    /// Fields: title, creator, rating, warning, category, fandom, relationship, character, tag,
+    /// language, series, source, status, summary, error, path, words, chapters, kudos, hits,
+    /// bookmarks, comments, reading_minutes, published, updated and completed
+    #[arg(long)]
+    pub filter: Option<FicFilter>,
+}
+
+impl ScanArgs {
+    pub fn spine_search(&self) -> SpineSearch {
+        match (self.whole_spine, self.search_depth) {
+            (true, _) => SpineSearch::WholeSpine,
+            (false, Some(depth)) => SpineSearch::FirstDocuments(depth),
+            (false, None) => SpineSearch::default(),
+        }
+    }
+
+    pub fn load_fics(&self) -> AnyResult<Vec<FullFicInfo>> {
+        Ok(FicFilter::apply(
+            self.filter.as_ref(),
+            self.load_all_fics()?,
+        ))
+    }
+
+    /// Loads the fics without applying the filter.
+    pub fn load_all_fics(&self) -> AnyResult<Vec<FullFicInfo>> {
+        if let Some(catalog_path) = &self.from_catalog {
+            return Catalog::open(catalog_path)?.load_fics();
+        }
+        match &self.cache {
+            Some(cache_path) => {
+                let mut cache = ScanCache::load(cache_path, self.hash);
+                let fics = scan_library(self.inputs.iter(), Some(&mut cache), self.spine_search());
+                cache.save(cache_path)?;
+                Ok(fics)
+            }
+            None => Ok(scan_library(self.inputs.iter(), None, self.spine_search())),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// File to write the catalog to
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Output format, guessed from the output file extension if omitted
+    #[arg(short, long, value_enum)]
+    pub format: Option<ExportFormat>,
+
+    #[command(flatten)]
+    pub options: ExportOptions,
+}
+
+#[derive(Debug, Args)]
+pub struct OrganizeArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    #[command(flatten)]
+    pub options: OrganizeOptions,
+}
+
+#[derive(Debug, Args)]
+pub struct WriteMetadataArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// Write modified copies into this directory instead of rewriting the epubs in place,
+    /// keeping the layout of the input directories
+    #[arg(long)]
+    pub copy_to: Option<PathBuf>,
+
+    /// Only print the epubs whose metadata would change
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+pub fn run(command: Command) -> AnyResult<()> {
+    match command {
+        Command::Scan(args) => {
+            for fic in args.load_fics()? {
+                println!(
+                    "{}\t{}\t{}",
+                    fic.meta_info.path_to_file.display(),
+                    fic.meta_info.title.as_deref().unwrap_or(""),
+                    match &fic.tags {
+                        Ok(_) => "ok".to_string(),
+                        Err(err) => format!("{}: {}", err.kind.name(), err),
+                    }
+                );
+            }
+        }
+        Command::Export(args) => {
+            let format = ExportFormat::resolve(args.format, &args.output)?;
+            let fics = args.scan.load_all_fics()?;
+            export_catalog(
+                &args.output,
+                format,
+                &args.options,
+                args.scan.filter.as_ref(),
+                &fics,
+            )?;
+        }
+        Command::Watch(args) => watch_library(&args)?,
+        Command::Stats(args) => {
+            let fics = args.load_fics()?;
+            let n_parsed = fics.iter().filter(|fic| fic.tags.is_ok()).count();
+            println!("epubs found:       {}", fics.len());
+            println!("tags parsed:       {}", n_parsed);
+            println!("tags not parsed:   {}", fics.len() - n_parsed);
+            let errors_by_kind = fics
+                .iter()
+                .filter_map(|fic| fic.tags.as_ref().err())
+                .counts_by(|err| err.kind);
+            for (kind, count) in errors_by_kind.into_iter().sorted() {
+                println!("  {:<17}{}", format!("{}:", kind.name()), count);
+            }
+        }
+        Command::Duplicates(args) => {
+            let fics = args.load_fics()?;
+            for group in find_duplicates(&fics) {
+                println!("{}", group.key);
+                for (i, fic) in group.fics.iter().enumerate() {
+                    println!(
+                        "  {} {}",
+                        if i == 0 { "newest" } else { "older " },
+                        fic.meta_info.path_to_file.display()
+                    );
+                }
+            }
+        }
+        Command::Organize(args) => organize(&args.scan.load_fics()?, &args.options)?,
+        Command::UndoOrganize { log } => undo_organize(log)?,
+        Command::WriteMetadata(args) => write_library_metadata(
+            &args.scan.load_fics()?,
+            &args.scan.inputs,
+            args.copy_to.as_deref(),
+            args.dry_run,
+        )?,
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use chrono::NaiveDate;
+use itertools::Itertools;
+use rust_xlsxwriter::{Format, Worksheet};
+
+use crate::{
+    serialization::FullFicInfo,
+    tags::{AO3Stats, CompletionStatus},
+};
+
+/// What makes two epubs copies of the same work.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DuplicateKey {
+    /// Canonical URL of the work, made from its identifier on the site it comes from, so that
+    /// parsers covering several sites do not mix up their identifiers
+    WorkUrl(String),
+    /// Normalized title and sorted normalized creators, used when there is no work ID
+    TitleAndCreators(String, Vec<String>),
+}
+
+impl DuplicateKey {
+    pub fn of(fic_info: &FullFicInfo) -> Option<Self> {
+        let meta_info = &fic_info.meta_info;
+        if let Some(work_url) = &meta_info.work_url {
+            return Some(Self::WorkUrl(work_url.clone()));
+        }
+        let title = normalize(meta_info.title.as_deref()?);
+        if title.is_empty() {
+            return None;
+        }
+        let creators = meta_info
+            .creators
+            .iter()
+            .map(|creator| normalize(creator))
+            .sorted()
+            .collect();
+        Some(Self::TitleAndCreators(title, creators))
+    }
+}
+
+impl std::fmt::Display for DuplicateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WorkUrl(work_url) => write!(f, "work {}", work_url),
+            Self::TitleAndCreators(title, creators) => {
+                write!(f, "\"{}\" by {}", title, creators.join(", "))
+            }
+        }
+    }
+}
+
+/// Lowercases `s` and collapses everything that is not alphanumeric into single spaces.
+fn normalize(s: &str) -> String {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .join(" ")
+}
+
+/// Several epubs of the same work, the newest and most complete copy first.
+#[derive(Debug)]
+pub struct DuplicateGroup<'a> {
+    pub key: DuplicateKey,
+    pub fics: Vec<&'a FullFicInfo>,
+}
+
+fn parsed_stats(fic_info: &FullFicInfo) -> Option<&AO3Stats> {
+    fic_info.tags.as_ref().ok()?.parsed_stats.as_ref()
+}
+
+/// Orders copies of a work: complete before in progress, then more chapters, then the most
+/// recent date, then more words.
+fn completeness(fic_info: &FullFicInfo) -> (bool, Option<u32>, Option<NaiveDate>, Option<u64>) {
+    let Some(stats) = parsed_stats(fic_info) else {
+        return (false, None, None, None);
+    };
+    (
+        stats.status == Some(CompletionStatus::Complete),
+        stats.chapters,
+        [stats.published, stats.updated, stats.completed]
+            .into_iter()
+            .flatten()
+            .max(),
+        stats.words,
+    )
+}
+
+/// Groups the fics that appear more than once, in the order their first copy was found.
+pub fn find_duplicates(fics: &[FullFicInfo]) -> Vec<DuplicateGroup<'_>> {
+    let mut groups = HashMap::<DuplicateKey, Vec<&FullFicInfo>>::new();
+    let mut keys_order = vec![];
+    for fic_info in fics {
+        let Some(key) = DuplicateKey::of(fic_info) else {
+            continue;
+        };
+        groups
+            .entry(key.clone())
+            .or_insert_with(|| {
+                keys_order.push(key);
+                vec![]
+            })
+            .push(fic_info);
+    }
+
+    keys_order
+        .into_iter()
+        .filter_map(|key| {
+            let mut fics = groups.remove(&key)?;
+            if fics.len() < 2 {
+                return None;
+            }
+            fics.sort_by_key(|fic_info| Reverse(completeness(fic_info)));
+            Some(DuplicateGroup { key, fics })
+        })
+        .collect()
+}
+
+const DUPLICATES_COLUMNS: [&str; 9] = [
+    "group",
+    "duplicate_of",
+    "keep",
+    "path_to_file",
+    "title",
+    "chapters",
+    "planned_chapters",
+    "updated",
+    "words",
+];
+
+pub fn write_duplicates_worksheet(
+    worksheet: &mut Worksheet,
+    groups: &[DuplicateGroup],
+) -> anyhow::Result<()> {
+    worksheet.set_name("duplicates")?;
+    worksheet.write_row_with_format(0, 0, DUPLICATES_COLUMNS, &Format::new().set_bold())?;
+
+    let mut row = 0;
+    for (i_group, group) in groups.iter().enumerate() {
+        for (i_fic, fic_info) in group.fics.iter().enumerate() {
+            row += 1;
+            let stats = parsed_stats(fic_info);
+            worksheet.write_number(row, 0, (i_group + 1) as f64)?;
+            worksheet.write_string(row, 1, group.key.to_string())?;
+            worksheet.write_string(row, 2, if i_fic == 0 { "newest" } else { "older" })?;
+            worksheet.write_string(row, 3, fic_info.meta_info.path_to_file.to_string_lossy())?;
+            worksheet.write_string(row, 4, fic_info.meta_info.title.as_deref().unwrap_or(""))?;
+            if let Some(chapters) = stats.and_then(|stats| stats.chapters) {
+                worksheet.write_number(row, 5, chapters)?;
+            }
+            if let Some(planned_chapters) = stats.and_then(|stats| stats.planned_chapters) {
+                worksheet.write_number(row, 6, planned_chapters)?;
+            }
+            if let Some(updated) = stats.and_then(|stats| stats.updated.or(stats.completed)) {
+                worksheet.write_string(row, 7, updated.to_string())?;
+            }
+            if let Some(words) = stats.and_then(|stats| stats.words) {
+                worksheet.write_number(row, 8, words as f64)?;
+            }
+        }
+    }
+    worksheet.autofit();
+    Ok(())
+}
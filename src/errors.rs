@@ -0,0 +1,82 @@
+use std::fmt;
+
+use rbook::result::{EbookError, ReaderError};
+
+/// Stage at which exploring an epub failed.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FicErrorKind {
+    /// The file is not a readable zip archive
+    Open,
+    /// The container or package document (OPF) is missing or malformed
+    Opf,
+    /// A spine document cannot be read from the archive
+    PageFetch,
+    /// A spine document is not well-formed XHTML
+    XmlParse,
+    /// No tag block was found in the searched spine documents
+    MissingTags,
+    /// The tag block does not have the expected layout
+    TagLayout,
+    /// The tag block holds the same tag twice
+    DuplicateTag,
+    /// Any other failure
+    Other,
+}
+
+impl FicErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Opf => "opf",
+            Self::PageFetch => "page_fetch",
+            Self::XmlParse => "xml_parse",
+            Self::MissingTags => "missing_tags",
+            Self::TagLayout => "tag_layout",
+            Self::DuplicateTag => "duplicate_tag",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Why an epub could not be fully explored, recorded in place of its tags.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FicError {
+    pub kind: FicErrorKind,
+    pub message: String,
+}
+
+impl FicError {
+    pub fn new<S: Into<String>>(kind: FicErrorKind, message: S) -> Self {
+        FicError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Recovers the typed error from an `anyhow` error, classifying the errors of `rbook`
+    /// and falling back to [`FicErrorKind::Other`].
+    pub fn from_anyhow(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<FicError>() {
+            Ok(fic_error) => return fic_error,
+            Err(err) => err,
+        };
+        let kind = match err.downcast_ref::<EbookError>() {
+            Some(EbookError::IO { .. }) => FicErrorKind::Open,
+            Some(EbookError::Parse { .. } | EbookError::Archive(_)) => FicErrorKind::Opf,
+            None if err.is::<ReaderError>() => FicErrorKind::PageFetch,
+            None => FicErrorKind::Other,
+        };
+        FicError::new(kind, err.to_string())
+    }
+}
+
+impl fmt::Display for FicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FicError {}
@@ -0,0 +1,161 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result as AnyResult};
+use log::info;
+
+use crate::{
+    catalog::Catalog,
+    filter::FicFilter,
+    get_data::write_workbook,
+    serialization::{cell_to_string, fic_to_table_row, FullFicInfo, ALL_TABLE_COLUMNS},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Xlsx,
+    Csv,
+    Tsv,
+    Json,
+    /// Newline-delimited JSON, one fic per line
+    Ndjson,
+    /// SQLite catalog, updated in place if it already exists
+    Sqlite,
+}
+
+impl ExportFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "xlsx" => Some(Self::Xlsx),
+            "csv" => Some(Self::Csv),
+            "tsv" | "tab" => Some(Self::Tsv),
+            "json" => Some(Self::Json),
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            "sqlite" | "sqlite3" | "db" => Some(Self::Sqlite),
+            _ => None,
+        }
+    }
+
+    /// Uses `format` if it is given, otherwise guesses it from `path`.
+    pub fn resolve<P: AsRef<Path>>(format: Option<Self>, path: P) -> AnyResult<Self> {
+        format.or_else(|| Self::from_path(&path)).ok_or_else(|| {
+            anyhow!(
+                "cannot infer export format from `{}`, please specify it explicitly",
+                path.as_ref().display()
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ExportOptions {
+    /// Field delimiter for delimited formats, defaults to `,` for csv and tab for tsv
+    #[arg(long)]
+    pub delimiter: Option<char>,
+
+    /// Separator placed between values of multi-valued fields in delimited formats
+    #[arg(long, default_value = "; ")]
+    pub multi_value_separator: String,
+}
+
+/// Exports the fics matching `filter`. SQLite catalogs are updated in place: the matching
+/// fics are stored and the others are removed from it, like works whose files no longer
+/// exist; other formats are rewritten with the matching fics.
+pub fn export_catalog<P: AsRef<Path>>(
+    output_path: P,
+    format: ExportFormat,
+    options: &ExportOptions,
+    filter: Option<&FicFilter>,
+    fics: &[FullFicInfo],
+) -> AnyResult<()> {
+    let is_kept = |fic_info: &&FullFicInfo| filter.is_none_or(|filter| filter.matches(fic_info));
+    let kept = || fics.iter().filter(is_kept).cloned().collect::<Vec<_>>();
+    match format {
+        ExportFormat::Xlsx => write_workbook(output_path, &kept()),
+        ExportFormat::Csv => write_delimited(
+            output_path,
+            options.delimiter.unwrap_or(','),
+            options,
+            &kept(),
+        ),
+        ExportFormat::Tsv => write_delimited(
+            output_path,
+            options.delimiter.unwrap_or('\t'),
+            options,
+            &kept(),
+        ),
+        ExportFormat::Json => write_json(output_path, &kept()),
+        ExportFormat::Ndjson => write_ndjson(output_path, &kept()),
+        ExportFormat::Sqlite => {
+            let (kept, dropped): (Vec<_>, Vec<_>) = fics.iter().partition(is_kept);
+            write_catalog(output_path, &kept, &dropped)
+        }
+    }
+}
+
+fn write_delimited<P: AsRef<Path>>(
+    output_path: P,
+    delimiter: char,
+    options: &ExportOptions,
+    fics: &[FullFicInfo],
+) -> AnyResult<()> {
+    let delimiter = u8::try_from(delimiter)
+        .map_err(|_| anyhow!("delimiter `{}` is not an ASCII character", delimiter))?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_path(output_path)?;
+
+    writer.write_record(ALL_TABLE_COLUMNS.iter())?;
+    for fic_info in fics {
+        let row = fic_to_table_row(fic_info, &options.multi_value_separator)?;
+        writer.write_record(row.iter().map(cell_to_string))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_json<P: AsRef<Path>>(output_path: P, fics: &[FullFicInfo]) -> AnyResult<()> {
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    let records: Vec<_> = fics.iter().map(FullFicInfo::as_json_record).collect();
+    serde_json::to_writer_pretty(&mut writer, &records)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_ndjson<P: AsRef<Path>>(output_path: P, fics: &[FullFicInfo]) -> AnyResult<()> {
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    for fic_info in fics {
+        serde_json::to_writer(&mut writer, &fic_info.as_json_record())?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_catalog<P: AsRef<Path>>(
+    output_path: P,
+    kept: &[&FullFicInfo],
+    dropped: &[&FullFicInfo],
+) -> AnyResult<()> {
+    let mut catalog = Catalog::open(output_path)?;
+    catalog.store_fics(kept.iter().copied())?;
+    let n_dropped = catalog.remove_fics(
+        &dropped
+            .iter()
+            .map(|fic_info| fic_info.meta_info.path_to_file.as_path())
+            .collect::<Vec<_>>(),
+    )?;
+    if n_dropped > 0 {
+        info!("removed {} works not matching the filter", n_dropped);
+    }
+    let n_pruned = catalog.prune_missing_files()?;
+    if n_pruned > 0 {
+        info!("removed {} works whose files no longer exist", n_pruned);
+    }
+    Ok(())
+}
@@ -0,0 +1,612 @@
+//! Filter expressions selecting fics, such as
+//! `rating:Explicit AND fandom:"Harry Potter" AND NOT warning:"Major Character Death" AND words>50000`.
+//!
+//! An expression is made of comparisons `field op value` combined with `AND`, `OR`, `NOT`
+//! and parentheses; `AND` may be left out between two comparisons. Text fields match when
+//! any of their values contains the given text (`:`) or equals it (`=`, `!=`), ignoring
+//! case. Number and date fields (`YYYY-MM-DD`) also accept `<`, `<=`, `>` and `>=`.
+
+use std::{iter::Peekable, str::FromStr};
+
+use anyhow::{anyhow, bail, Result as AnyResult};
+use chrono::NaiveDate;
+
+use crate::{
+    serialization::FullFicInfo,
+    tags::{AO3Stats, CompletionStatus, ParsedAO3Tags},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Title,
+    Creator,
+    Rating,
+    Warning,
+    Category,
+    Fandom,
+    Relationship,
+    Character,
+    AdditionalTag,
+    Language,
+    Series,
+    Source,
+    Status,
+    Summary,
+    Error,
+    Path,
+    Words,
+    Chapters,
+    Kudos,
+    Hits,
+    Bookmarks,
+    Comments,
+    ReadingMinutes,
+    Published,
+    Updated,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    Text,
+    Number,
+    Date,
+}
+
+/// Names accepted for each field, with their type.
+const FIELDS: &[(&str, Field, FieldType)] = &[
+    ("title", Field::Title, FieldType::Text),
+    ("creator", Field::Creator, FieldType::Text),
+    ("author", Field::Creator, FieldType::Text),
+    ("rating", Field::Rating, FieldType::Text),
+    ("warning", Field::Warning, FieldType::Text),
+    ("category", Field::Category, FieldType::Text),
+    ("fandom", Field::Fandom, FieldType::Text),
+    ("relationship", Field::Relationship, FieldType::Text),
+    ("ship", Field::Relationship, FieldType::Text),
+    ("character", Field::Character, FieldType::Text),
+    ("tag", Field::AdditionalTag, FieldType::Text),
+    ("freeform", Field::AdditionalTag, FieldType::Text),
+    ("language", Field::Language, FieldType::Text),
+    ("series", Field::Series, FieldType::Text),
+    ("source", Field::Source, FieldType::Text),
+    ("status", Field::Status, FieldType::Text),
+    ("summary", Field::Summary, FieldType::Text),
+    ("error", Field::Error, FieldType::Text),
+    ("path", Field::Path, FieldType::Text),
+    ("words", Field::Words, FieldType::Number),
+    ("chapters", Field::Chapters, FieldType::Number),
+    ("kudos", Field::Kudos, FieldType::Number),
+    ("hits", Field::Hits, FieldType::Number),
+    ("bookmarks", Field::Bookmarks, FieldType::Number),
+    ("comments", Field::Comments, FieldType::Number),
+    ("reading_minutes", Field::ReadingMinutes, FieldType::Number),
+    ("published", Field::Published, FieldType::Date),
+    ("updated", Field::Updated, FieldType::Date),
+    ("completed", Field::Completed, FieldType::Date),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Contains,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl CompareOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Contains => ":",
+            Self::Equal => "=",
+            Self::NotEqual => "!=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn is_ordering(self) -> bool {
+        !matches!(self, Self::Contains | Self::Equal | Self::NotEqual)
+    }
+
+    fn compare<T: PartialOrd>(self, actual: T, expected: T) -> bool {
+        match self {
+            Self::Contains | Self::Equal => actual == expected,
+            Self::NotEqual => actual != expected,
+            Self::Less => actual < expected,
+            Self::LessOrEqual => actual <= expected,
+            Self::Greater => actual > expected,
+            Self::GreaterOrEqual => actual >= expected,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    /// Lowercased, as text comparisons ignore case
+    Text(String),
+    Number(u64),
+    Date(NaiveDate),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        field: Field,
+        op: CompareOp,
+        value: Value,
+    },
+}
+
+/// A parsed filter expression, see the module documentation for the syntax.
+#[derive(Debug, Clone)]
+pub struct FicFilter {
+    expr: Expr,
+}
+
+impl FromStr for FicFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> AnyResult<Self> {
+        let mut tokens = tokenize(s)?.into_iter().peekable();
+        let expr = parse_or(&mut tokens)?;
+        if let Some(token) = tokens.next() {
+            bail!("unexpected {} in filter", token.describe());
+        }
+        Ok(FicFilter { expr })
+    }
+}
+
+impl FicFilter {
+    pub fn matches(&self, fic_info: &FullFicInfo) -> bool {
+        self.expr.matches(fic_info)
+    }
+
+    /// Keeps the fics matching `filter`, or all of them without a filter.
+    pub fn apply(filter: Option<&Self>, fics: Vec<FullFicInfo>) -> Vec<FullFicInfo> {
+        match filter {
+            Some(filter) => fics
+                .into_iter()
+                .filter(|fic_info| filter.matches(fic_info))
+                .collect(),
+            None => fics,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(CompareOp),
+    OpenParen,
+    CloseParen,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("`{}`", word),
+            Token::Quoted(text) => format!("\"{}\"", text),
+            Token::Op(op) => format!("`{}`", op.symbol()),
+            Token::OpenParen => "`(`".into(),
+            Token::CloseParen => "`)`".into(),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+fn tokenize(s: &str) -> AnyResult<Vec<Token>> {
+    let is_special = |c: char| c.is_whitespace() || "()\":=!<>".contains(c);
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ':' => Token::Op(CompareOp::Contains),
+            '=' => Token::Op(CompareOp::Equal),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::NotEqual),
+            '!' => bail!("expected `!=` in filter"),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::LessOrEqual),
+            '<' => Token::Op(CompareOp::Less),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::GreaterOrEqual),
+            '>' => Token::Op(CompareOp::Greater),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => bail!("unterminated quoted text in filter"),
+                    }
+                }
+                Token::Quoted(text)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|&c| !is_special(c)) {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+fn parse_or(tokens: &mut Tokens) -> AnyResult<Expr> {
+    let mut expr = parse_and(tokens)?;
+    while tokens.next_if(|token| token.is_keyword("or")).is_some() {
+        expr = Expr::Or(Box::new(expr), Box::new(parse_and(tokens)?));
+    }
+    Ok(expr)
+}
+
+fn parse_and(tokens: &mut Tokens) -> AnyResult<Expr> {
+    let mut expr = parse_unary(tokens)?;
+    loop {
+        if tokens.next_if(|token| token.is_keyword("and")).is_none() {
+            match tokens.peek() {
+                None | Some(Token::CloseParen) => break,
+                Some(token) if token.is_keyword("or") => break,
+                _ => (),
+            }
+        }
+        expr = Expr::And(Box::new(expr), Box::new(parse_unary(tokens)?));
+    }
+    Ok(expr)
+}
+
+fn parse_unary(tokens: &mut Tokens) -> AnyResult<Expr> {
+    match tokens.next() {
+        Some(token) if token.is_keyword("not") => Ok(Expr::Not(Box::new(parse_unary(tokens)?))),
+        Some(Token::OpenParen) => {
+            let expr = parse_or(tokens)?;
+            match tokens.next() {
+                Some(Token::CloseParen) => Ok(expr),
+                Some(token) => bail!("expected `)` in filter, found {}", token.describe()),
+                None => bail!("missing `)` at the end of the filter"),
+            }
+        }
+        Some(Token::Word(name)) => parse_comparison(&name, tokens),
+        Some(token) => bail!(
+            "expected a field name in filter, found {}",
+            token.describe()
+        ),
+        None => bail!("filter ends where a field name is expected"),
+    }
+}
+
+fn parse_comparison(name: &str, tokens: &mut Tokens) -> AnyResult<Expr> {
+    let (_, field, field_type) = FIELDS
+        .iter()
+        .find(|(field_name, _, _)| field_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown filter field `{}`, expected one of: {}",
+                name,
+                FIELDS
+                    .iter()
+                    .map(|(name, _, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+    let op = match tokens.next() {
+        Some(Token::Op(op)) => op,
+        _ => bail!(
+            "expected `:`, `=`, `!=`, `<`, `<=`, `>` or `>=` after `{}`",
+            name
+        ),
+    };
+    let text = match tokens.next() {
+        Some(Token::Word(text) | Token::Quoted(text)) => text,
+        _ => bail!("expected a value after `{}{}`", name, op.symbol()),
+    };
+    let value = match field_type {
+        FieldType::Text if op.is_ordering() => {
+            bail!(
+                "`{}` is a text field, it cannot be compared with `{}`",
+                name,
+                op.symbol()
+            )
+        }
+        FieldType::Text => Value::Text(text.to_lowercase()),
+        FieldType::Number => Value::Number(
+            text.replace([',', '_'], "")
+                .parse()
+                .map_err(|_| anyhow!("`{}` expects a number, not `{}`", name, text))?,
+        ),
+        FieldType::Date => Value::Date(
+            NaiveDate::parse_from_str(&text, "%Y-%m-%d")
+                .map_err(|_| anyhow!("`{}` expects a YYYY-MM-DD date, not `{}`", name, text))?,
+        ),
+    };
+    Ok(Expr::Compare {
+        field: *field,
+        op,
+        value,
+    })
+}
+
+impl Expr {
+    fn matches(&self, fic_info: &FullFicInfo) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(fic_info) && right.matches(fic_info),
+            Expr::Or(left, right) => left.matches(fic_info) || right.matches(fic_info),
+            Expr::Not(expr) => !expr.matches(fic_info),
+            Expr::Compare { field, op, value } => match value {
+                Value::Text(expected) => {
+                    let mut texts = field.texts(fic_info).into_iter().map(|s| s.to_lowercase());
+                    match op {
+                        CompareOp::Contains => texts.any(|text| text.contains(expected.as_str())),
+                        CompareOp::NotEqual => !texts.any(|text| text == *expected),
+                        _ => texts.any(|text| text == *expected),
+                    }
+                }
+                Value::Number(expected) => field
+                    .number(fic_info)
+                    .is_some_and(|actual| op.compare(actual, *expected)),
+                Value::Date(expected) => field
+                    .date(fic_info)
+                    .is_some_and(|actual| op.compare(actual, *expected)),
+            },
+        }
+    }
+}
+
+fn parsed_stats(fic_info: &FullFicInfo) -> Option<&AO3Stats> {
+    fic_info.tags.as_ref().ok()?.parsed_stats.as_ref()
+}
+
+impl Field {
+    fn texts(self, fic_info: &FullFicInfo) -> Vec<String> {
+        let meta_info = &fic_info.meta_info;
+        let tags = fic_info.tags.as_ref();
+        let tag_list = |list: fn(&ParsedAO3Tags) -> &Vec<String>| {
+            tags.map(|tags| list(tags).clone()).unwrap_or_default()
+        };
+        match self {
+            Field::Title => meta_info.title.iter().cloned().collect(),
+            Field::Creator => meta_info.creators.clone(),
+            Field::Rating => tags
+                .ok()
+                .and_then(|tags| tags.rating.clone())
+                .into_iter()
+                .collect(),
+            Field::Warning => tag_list(|tags| &tags.archive_warnings),
+            Field::Category => tag_list(|tags| &tags.categories),
+            Field::Fandom => tag_list(|tags| &tags.fandoms),
+            Field::Relationship => tag_list(|tags| &tags.relationships),
+            Field::Character => tag_list(|tags| &tags.characters),
+            Field::AdditionalTag => tag_list(|tags| &tags.additional_tags),
+            Field::Language => tags
+                .ok()
+                .and_then(|tags| tags.language.clone())
+                .into_iter()
+                .collect(),
+            Field::Series => tags
+                .map(|tags| {
+                    tags.series
+                        .iter()
+                        .map(|series| series.name.clone())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            Field::Source => meta_info.source.iter().cloned().collect(),
+            Field::Status => parsed_stats(fic_info)
+                .and_then(|stats| stats.status)
+                .map(|status| match status {
+                    CompletionStatus::Complete => "complete".to_string(),
+                    CompletionStatus::InProgress => "in progress".to_string(),
+                })
+                .into_iter()
+                .collect(),
+            Field::Summary => tags
+                .ok()
+                .and_then(|tags| tags.summary.clone())
+                .or_else(|| meta_info.description.clone())
+                .into_iter()
+                .collect(),
+            Field::Error => tags
+                .err()
+                .map(|err| err.kind.name().to_string())
+                .into_iter()
+                .collect(),
+            Field::Path => vec![meta_info.path_to_file.to_string_lossy().into()],
+            _ => vec![],
+        }
+    }
+
+    fn number(self, fic_info: &FullFicInfo) -> Option<u64> {
+        let stats = parsed_stats(fic_info);
+        match self {
+            // The count declared by the archive, or the one made from the text.
+            Field::Words => stats
+                .and_then(|stats| stats.words)
+                .or(fic_info.meta_info.local_words),
+            Field::Chapters => stats
+                .and_then(|stats| stats.chapters)
+                .map(u64::from)
+                .or_else(|| Some(fic_info.chapters.len() as u64).filter(|&n| n > 0)),
+            Field::Kudos => stats?.kudos,
+            Field::Hits => stats?.hits,
+            Field::Bookmarks => stats?.bookmarks,
+            Field::Comments => stats?.comments,
+            Field::ReadingMinutes => fic_info.meta_info.reading_minutes,
+            _ => None,
+        }
+    }
+
+    fn date(self, fic_info: &FullFicInfo) -> Option<NaiveDate> {
+        let stats = parsed_stats(fic_info)?;
+        match self {
+            Field::Published => stats.published,
+            Field::Updated => stats.updated,
+            Field::Completed => stats.completed,
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(filter: &str, fic_info: &FullFicInfo) -> bool {
+        filter.parse::<FicFilter>().unwrap().matches(fic_info)
+    }
+
+    fn parse_error(filter: &str) -> String {
+        filter.parse::<FicFilter>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_tighter_than_or() {
+        let explicit = FullFicInfo::titled("A")
+            .rated("Explicit")
+            .in_fandoms(&["Harry Potter"])
+            .with_words(100);
+        // NOT applies to the first comparison only
+        assert!(matches("NOT rating:teen AND fandom:potter", &explicit));
+        // Read as `rating:teen OR (fandom:potter AND words>50)`
+        assert!(matches(
+            "rating:teen OR fandom:potter AND words>50",
+            &explicit
+        ));
+        // Read as `(rating:explicit AND words>500) OR title=a`
+        assert!(matches(
+            "rating:explicit AND words>500 OR title=a",
+            &explicit
+        ));
+        assert!(!matches(
+            "rating:explicit AND words>500 OR title=b",
+            &explicit
+        ));
+    }
+
+    #[test]
+    fn and_may_be_left_out() {
+        let explicit = FullFicInfo::titled("A")
+            .rated("Explicit")
+            .in_fandoms(&["Harry Potter"])
+            .with_words(100);
+        assert!(matches("rating:explicit fandom:potter", &explicit));
+        assert!(!matches("rating:explicit fandom:marvel", &explicit));
+        assert!(matches(
+            "rating:teen OR rating:explicit words<200",
+            &explicit
+        ));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let explicit = FullFicInfo::titled("A")
+            .rated("Explicit")
+            .in_fandoms(&["Harry Potter"])
+            .with_words(100);
+        assert!(!matches(
+            "(rating:teen OR fandom:potter) AND words>500",
+            &explicit
+        ));
+        assert!(matches("NOT (rating:teen OR words>500)", &explicit));
+        assert!(matches("((rating:explicit))", &explicit));
+    }
+
+    #[test]
+    fn quoted_values_keep_spaces_and_escapes() {
+        let fic_info =
+            FullFicInfo::titled("Say \"Hi\"").in_fandoms(&["Harry Potter - J. K. Rowling"]);
+        assert!(matches(
+            r#"fandom="harry potter - j. k. rowling""#,
+            &fic_info
+        ));
+        assert!(matches(r#"title:"say \"hi\"""#, &fic_info));
+        // Quoted keywords are values, not operators
+        assert!(!matches(r#"fandom:"and""#, &fic_info));
+        assert!(matches(r#"fandom:"Row" OR fandom:"not""#, &fic_info));
+        assert!(!matches(r#"fandom="harry potter""#, &fic_info));
+    }
+
+    #[test]
+    fn compares_numbers_and_dates() {
+        let fic_info = FullFicInfo::titled("A")
+            .with_words(50_000)
+            .published(NaiveDate::from_ymd_opt(2020, 6, 15).unwrap());
+        assert!(matches("words>=50,000", &fic_info));
+        assert!(matches("words=50000", &fic_info));
+        assert!(!matches("words>50000", &fic_info));
+        assert!(matches("words<50001 AND words!=1", &fic_info));
+        assert!(matches(
+            "published>2020-06-14 published<=2020-06-15",
+            &fic_info
+        ));
+        assert!(!matches("published<2020-01-01", &fic_info));
+    }
+
+    #[test]
+    fn missing_values() {
+        let unrated = FullFicInfo::titled("A");
+        // No value equals the text
+        assert!(matches("rating!=explicit", &unrated));
+        assert!(!matches("rating=explicit", &unrated));
+        // Number and date comparisons need a value
+        assert!(!matches("words<10", &unrated));
+        assert!(!matches("words!=10", &unrated));
+        assert!(!matches("completed>2000-01-01", &unrated));
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        assert!(parse_error("foo:bar")
+            .starts_with("unknown filter field `foo`, expected one of: title, "));
+        assert_eq!(
+            parse_error("words>abc"),
+            "`words` expects a number, not `abc`"
+        );
+        assert_eq!(
+            parse_error("published>yesterday"),
+            "`published` expects a YYYY-MM-DD date, not `yesterday`"
+        );
+        assert_eq!(
+            parse_error("rating>teen"),
+            "`rating` is a text field, it cannot be compared with `>`"
+        );
+        assert_eq!(parse_error("rating:"), "expected a value after `rating:`");
+        assert_eq!(
+            parse_error("(rating:teen"),
+            "missing `)` at the end of the filter"
+        );
+        assert_eq!(
+            parse_error("title:\"open"),
+            "unterminated quoted text in filter"
+        );
+        assert_eq!(parse_error("rating:teen)"), "unexpected `)` in filter");
+        assert_eq!(
+            parse_error("rating teen"),
+            "expected `:`, `=`, `!=`, `<`, `<=`, `>` or `>=` after `rating`"
+        );
+        assert_eq!(parse_error("rating!teen"), "expected `!=` in filter");
+        assert_eq!(
+            parse_error("rating:teen AND"),
+            "filter ends where a field name is expected"
+        );
+    }
+}
@@ -4,13 +4,13 @@ use iced::Element;
 use iced::{
     alignment::Horizontal,
     color,
-    widget::{button, center, column, container, scrollable, text},
+    widget::{button, center, column, container, scrollable, text, text_input},
     Size, Task,
 };
 use itertools::Itertools;
 use log::info;
 
-use crate::get_data::generate_workbook;
+use crate::{filter::FicFilter, get_data::generate_workbook};
 
 pub fn main() -> iced::Result {
     iced::application("Checkbox - Iced", State::update, State::view)
@@ -28,6 +28,7 @@ type GenerationResult = Result<(), String>;
 struct State {
     picked_paths: Vec<PathBuf>,
     xlsx_path: Option<PathBuf>,
+    filter: String,
     processing: bool,
     generation_result: Option<GenerationResult>,
 }
@@ -37,13 +38,23 @@ struct State {
 enum Message {
     PickedPaths,
     PickedXlsxPath,
+    FilterChanged(String),
     Pass,
     Process,
     Generated(GenerationResult),
 }
 
-async fn gen_wb(xlsx_path: Option<PathBuf>, picked_paths: Vec<PathBuf>) -> GenerationResult {
-    generate_workbook(xlsx_path.unwrap(), picked_paths.iter()).map_err(|e| e.to_string())
+async fn gen_wb(
+    xlsx_path: Option<PathBuf>,
+    picked_paths: Vec<PathBuf>,
+    filter: String,
+) -> GenerationResult {
+    let filter = match filter.trim() {
+        "" => None,
+        filter => Some(filter.parse::<FicFilter>().map_err(|e| e.to_string())?),
+    };
+    generate_workbook(xlsx_path.unwrap(), picked_paths.iter(), filter.as_ref())
+        .map_err(|e| e.to_string())
 }
 
 impl State {
@@ -57,29 +68,33 @@ impl State {
                 self.xlsx_path = select_xlsx_file();
                 Task::none()
             }
+            Message::FilterChanged(filter) => {
+                self.filter = filter;
+                Task::none()
+            }
             Message::Pass => Task::none(),
             Message::Process => {
                 self.processing = true;
                 info!("processing");
                 Task::perform(
-                    gen_wb(self.xlsx_path.clone(), self.picked_paths.clone()),
+                    gen_wb(
+                        self.xlsx_path.clone(),
+                        self.picked_paths.clone(),
+                        self.filter.clone(),
+                    ),
                     Message::Generated,
                 )
             }
             Message::Generated(res) => {
                 info!("generated!");
                 self.processing = false;
-                self.generation_result = Some(res.clone());
-                match res {
-                    Ok(()) => (),
-                    Err(_) => (),
-                }
+                self.generation_result = Some(res);
                 Task::none()
             }
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let get_files_button =
             button(text("Select epub files to process")).on_press(Message::PickedPaths);
         let selected_files_text =
@@ -91,6 +106,13 @@ impl State {
         let get_xlsx_button =
             button(text("Select where to write result")).on_press(Message::PickedXlsxPath);
 
+        let filter_input = text_input(
+            "Filter, e.g. rating:Explicit AND fandom:\"Harry Potter\" AND words>50000",
+            &self.filter,
+        )
+        .on_input(Message::FilterChanged)
+        .size(12);
+
         let process_button = button(text("Process files")).on_press_maybe(
             if !self.processing && !self.picked_paths.is_empty() && self.xlsx_path.is_some() {
                 Some(Message::Process)
@@ -111,6 +133,7 @@ impl State {
                 get_files_button,
                 selected_files_text,
                 get_xlsx_button,
+                filter_input,
                 process_button,
                 result
             ]
@@ -137,7 +160,7 @@ fn select_xlsx_file() -> Option<PathBuf> {
         .save_file()
 }
 
-fn format_paths(v: &Vec<PathBuf>) -> String {
+fn format_paths(v: &[PathBuf]) -> String {
     v.iter()
         .map(|p| {
             p.clone()
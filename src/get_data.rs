@@ -1,36 +1,86 @@
-use std::{
-    collections::{HashMap, HashSet},
-    path::Path,
-    usize,
-};
+use std::path::Path;
 
-use anyhow::{anyhow, bail, Result as AnyResult};
-use itertools::Itertools;
+use anyhow::Result as AnyResult;
 use log::{info, warn};
+use rayon::prelude::*;
 use rbook::{xml::Element, Ebook, Epub};
-use roxmltree::Node;
 use rust_xlsxwriter::{Format, Workbook, Worksheet};
 use walkdir::{DirEntry, WalkDir};
 
 use crate::{
+    cache::ScanCache,
+    chapters::{list_chapters, write_chapters_worksheet, ChapterInfo, TextLength},
+    duplicates::{find_duplicates, write_duplicates_worksheet},
+    errors::FicError,
     serialization::{
-        write_fic_to_worksheet_row, write_headers, FicMetaInfo, FullFicInfo, ALL_TABLE_COLUMNS,
+        tag_frequencies, write_fic_to_worksheet_row, write_headers, write_tag_frequency_worksheet,
+        write_tag_links_worksheet, FicMetaInfo, FullFicInfo, ALL_TABLE_COLUMNS, FREQUENCY_KINDS,
     },
-    tags::{AO3Tag, ParsedAO3Tags},
-    utils::full_node_text,
+    sources::{parse_tags_with_detected_source, SpineSearch, SpineTexts},
+    tags::{ParsedAO3Tags, Relationship},
 };
 
-pub fn explore_epub<P: AsRef<Path>>(path: P) -> AnyResult<FullFicInfo> {
+pub fn explore_epub<P: AsRef<Path>>(path: P, search: SpineSearch) -> AnyResult<FullFicInfo> {
     let epub = rbook::Epub::new(&path)?;
-    let meta_info = extract_fic_meta_info(&path, &epub);
-    let tags = extract_fic_tags(&epub);
+    let spine_texts = SpineTexts::read(&epub);
+    let spine = spine_texts.parse();
+    let mut meta_info = extract_fic_meta_info(&path, &epub);
+    let (source, tags) = parse_tags_with_detected_source(&epub, &spine, search);
+    let tags = tags.map(|tags| ParsedAO3Tags {
+        parsed_relationships: tags
+            .relationships
+            .iter()
+            .map(|relationship| Relationship::parse(relationship))
+            .collect(),
+        ..tags
+    });
+    if let Some(source) = source {
+        meta_info.source = Some(source.name().into());
+        meta_info.work_id = source.work_id(&epub, &spine);
+        meta_info.work_url = meta_info.work_id.map(|id| source.work_url(&epub, id));
+    }
+    let chapters = list_chapters(&epub, &spine);
+    let declared_words = tags
+        .as_ref()
+        .ok()
+        .and_then(|tags| tags.parsed_stats.as_ref()?.words);
+    add_text_length(&mut meta_info, &chapters, declared_words);
     Ok(FullFicInfo {
         meta_info,
-        tags: tags.map_err(|err| err.to_string()),
+        tags: tags.map_err(FicError::from_anyhow),
+        chapters,
     })
-    // match extract_fic_tags(&epub) {
-    //     Ok(tags) => Ok(FullFicInfo { meta_info, tags }),
-    // };
+}
+
+/// Relative difference between the declared and the counted word counts above which they are
+/// flagged as mismatching.
+const MAX_WORD_COUNT_MISMATCH: f64 = 0.2;
+
+fn add_text_length(
+    meta_info: &mut FicMetaInfo,
+    chapters: &[ChapterInfo],
+    declared_words: Option<u64>,
+) {
+    if chapters.is_empty() {
+        return;
+    }
+    let length: TextLength = chapters.iter().map(|chapter| chapter.length).sum();
+    meta_info.local_words = Some(length.words);
+    meta_info.local_characters = Some(length.characters);
+    meta_info.reading_minutes = Some(length.reading_minutes());
+    meta_info.word_count_mismatch = declared_words.map(|declared_words| {
+        let difference = length.words.abs_diff(declared_words) as f64;
+        let mismatch = difference > MAX_WORD_COUNT_MISMATCH * declared_words.max(1) as f64;
+        if mismatch {
+            warn!(
+                "`{}` declares {} words but {} were counted",
+                meta_info.path_to_file.display(),
+                declared_words,
+                length.words
+            );
+        }
+        mismatch
+    });
 }
 
 fn walk_paths_with_epubs<IP: Iterator<Item: AsRef<Path>>>(
@@ -38,6 +88,7 @@ fn walk_paths_with_epubs<IP: Iterator<Item: AsRef<Path>>>(
 ) -> impl Iterator<Item = DirEntry> {
     paths.flat_map(|path| {
         WalkDir::new(path)
+            .sort_by_file_name()
             .into_iter()
             .filter_map(|e| e.ok())
             .filter(|subpath| {
@@ -50,11 +101,77 @@ fn walk_paths_with_epubs<IP: Iterator<Item: AsRef<Path>>>(
     })
 }
 
-pub fn generate_workbook<P, IP>(workbook_path: P, epub_files_paths: IP) -> AnyResult<()>
+/// Explores every epub found under `epub_files_paths`. The ones that cannot be opened at
+/// all are kept with only their path and the error.
+///
+/// Epubs are explored in parallel, but the result keeps the order in which they were found.
+/// When a `cache` is given, unchanged epubs are taken from it instead of being parsed again,
+/// and the cache is updated with the newly explored ones. `search` tells which spine
+/// documents are searched for the tags.
+pub fn scan_library<IP>(
+    epub_files_paths: IP,
+    cache: Option<&mut ScanCache>,
+    search: SpineSearch,
+) -> Vec<FullFicInfo>
 where
-    P: AsRef<Path>,
     IP: Iterator<Item: AsRef<Path>>,
 {
+    let cache_view = cache.as_deref();
+    let scanned: Vec<_> = walk_paths_with_epubs(epub_files_paths)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|fic| {
+            let fingerprint = match cache_view.map(|cache| cache.lookup(fic.path(), search)) {
+                Some(Ok((fingerprint, Some((fic_info, cached_search))))) => {
+                    info!(
+                        "taking epub file `{}` from cache",
+                        fic.path().to_str().unwrap_or("")
+                    );
+                    return (Some((fingerprint, cached_search)), fic_info);
+                }
+                Some(Ok((fingerprint, None))) => Some((fingerprint, search)),
+                Some(Err(e)) => {
+                    warn!("cannot check cache for `{}`: {}", fic.path().display(), e);
+                    None
+                }
+                None => None,
+            };
+            info!(
+                "exploring epub file `{}`...",
+                fic.path().to_str().unwrap_or("")
+            );
+            match explore_epub(fic.path(), search) {
+                Ok(fic_info) => {
+                    info!("{:?}", fic_info);
+                    (fingerprint, fic_info)
+                }
+                Err(e) => {
+                    warn!("cannot explore `{}`: {}", fic.path().display(), e);
+                    let fic_info = FullFicInfo {
+                        meta_info: FicMetaInfo {
+                            path_to_file: fic.path().to_path_buf(),
+                            ..Default::default()
+                        },
+                        tags: Err(FicError::from_anyhow(e)),
+                        chapters: vec![],
+                    };
+                    (fingerprint, fic_info)
+                }
+            }
+        })
+        .collect();
+
+    if let Some(cache) = cache {
+        for (fingerprint, fic_info) in &scanned {
+            if let Some((fingerprint, search)) = fingerprint {
+                cache.insert(fingerprint.clone(), fic_info.clone(), *search);
+            }
+        }
+    }
+    scanned.into_iter().map(|(_, fic_info)| fic_info).collect()
+}
+
+pub fn write_workbook<P: AsRef<Path>>(workbook_path: P, fics: &[FullFicInfo]) -> AnyResult<()> {
     let mut workbook = Workbook::new();
 
     // Add a worksheet to the workbook.
@@ -62,21 +179,8 @@ where
 
     write_headers(worksheet)?;
 
-    for (i, fic) in walk_paths_with_epubs(epub_files_paths).enumerate() {
-        info!(
-            "exploring epub file `{}`...",
-            fic.path().to_str().unwrap_or("")
-        );
-        match explore_epub(fic.path()) {
-            Ok(fic_info) => {
-                info!("{:?}", fic_info);
-                write_fic_to_worksheet_row(worksheet, i + 1, &fic_info);
-            }
-            Err(e) => {
-                warn!("{}", e);
-                continue;
-            }
-        };
+    for (i, fic_info) in fics.iter().enumerate() {
+        write_fic_to_worksheet_row(worksheet, i + 1, fic_info);
     }
 
     worksheet.set_column_range_format(
@@ -86,10 +190,59 @@ where
     )?;
     worksheet.autofit();
 
+    if fics.iter().any(|fic_info| {
+        fic_info
+            .tags
+            .as_ref()
+            .is_ok_and(|tags| !tags.tag_links.is_empty())
+    }) {
+        write_tag_links_worksheet(workbook.add_worksheet(), fics)?;
+    }
+
+    if fics.iter().any(|fic_info| !fic_info.chapters.is_empty()) {
+        write_chapters_worksheet(workbook.add_worksheet(), fics)?;
+    }
+
+    for kind in FREQUENCY_KINDS {
+        let frequencies = tag_frequencies(fics, kind);
+        if !frequencies.is_empty() {
+            write_tag_frequency_worksheet(
+                workbook.add_worksheet(),
+                kind,
+                &frequencies,
+                kind.n_works(fics),
+            )?;
+        }
+    }
+
+    let duplicates = find_duplicates(fics);
+    if !duplicates.is_empty() {
+        write_duplicates_worksheet(workbook.add_worksheet(), &duplicates)?;
+    }
+
     workbook.save(workbook_path)?;
     Ok(())
 }
 
+/// Explores the epubs under `epub_files_paths` and writes the ones matching `filter` (all of
+/// them without a filter) to the workbook.
+#[cfg(not(feature = "no_gui"))]
+pub fn generate_workbook<P, IP>(
+    workbook_path: P,
+    epub_files_paths: IP,
+    filter: Option<&crate::filter::FicFilter>,
+) -> AnyResult<()>
+where
+    P: AsRef<Path>,
+    IP: Iterator<Item: AsRef<Path>>,
+{
+    let fics = scan_library(epub_files_paths, None, SpineSearch::default());
+    write_workbook(
+        workbook_path,
+        &crate::filter::FicFilter::apply(filter, fics),
+    )
+}
+
 fn extract_fic_meta_info<P: AsRef<Path>>(path: P, epub: &Epub) -> FicMetaInfo {
     fn extract_vec(v: Vec<&Element>) -> Vec<String> {
         v.into_iter().map(|elt| elt.value().into()).collect()
@@ -112,80 +265,6 @@ fn extract_fic_meta_info<P: AsRef<Path>>(path: P, epub: &Epub) -> FicMetaInfo {
                 })
             })
         },
+        ..Default::default()
     }
 }
-
-fn extract_fic_tags(epub: &Epub) -> AnyResult<ParsedAO3Tags> {
-    let reader = epub.reader();
-    let content = match reader.fetch_page(0) {
-        Some(Ok(content)) => content,
-        None => bail!("could not match first page of document due to lack of such"),
-        Some(Err(err)) => bail!(err),
-    };
-    let content_str = content.as_lossy_str();
-    let doc = roxmltree::Document::parse_with_options(
-        &content_str,
-        roxmltree::ParsingOptions {
-            allow_dtd: true,
-            ..Default::default()
-        },
-    )
-    .map_err(|err| anyhow!("error during parsing: {}", err))?;
-
-    let tags = doc
-        .root()
-        .descendants()
-        .find(|it| it.has_tag_name("dl") && it.attribute("class").unwrap_or("") == "tags")
-        .ok_or_else(|| anyhow!("cannot parse document tags"))?;
-
-    let (tags_with_nodes, unknown_tags) = process_dt_dd_elements_to_hash_map(&tags)?;
-    if !unknown_tags.is_empty() {
-        warn!("Unknown tags encountered: {:?}", unknown_tags)
-    }
-
-    Ok(ParsedAO3Tags::from_hash_map_of_ao3tags(&tags_with_nodes))
-}
-
-/// here `node` is expected to be a `<dl class="tags">` element
-fn process_dt_dd_elements_to_hash_map<'a>(
-    node: &'a Node<'a, 'a>,
-) -> AnyResult<(HashMap<AO3Tag<&'a str>, Node<'a, 'a>>, HashSet<&'a str>)> {
-    assert_eq!(node.attribute("class"), Some("tags"));
-    let mut result = HashMap::<AO3Tag<&'a str>, Node<'a, 'a>>::new();
-    let mut unknown_tags = HashSet::<&'a str>::new();
-
-    for (dt, dd) in node
-        .children()
-        // we're only interested in `<dt ...>` and `<dd ...>`
-        .filter(|elt| elt.is_element() && ["dt", "dd"].contains(&elt.tag_name().name()))
-        .tuples::<(_, _)>()
-    {
-        if dt.tag_name().name() != "dt" || dd.tag_name().name() != "dd" {
-            bail!(
-                "Tag pair mismatch during parsing: expected `(<dt ...>, <dd ...>)`, got `({}, {})`",
-                full_node_text(&dt),
-                full_node_text(&dd)
-            )
-        }
-        // println!("{}", dt.text().unwrap());
-        let tag_text = dt
-            .text()
-            .ok_or_else(|| anyhow!("cannot find text of element `{:?}`", dt))?;
-        let tag = AO3Tag::match_str(tag_text);
-        if result.contains_key(&tag) {
-            match tag {
-                AO3Tag::UnknownTag(unknown_tag) => unknown_tags.insert(unknown_tag),
-                _ => bail!(
-                    "several values found for tag `{}`, namely `{}` and `{}`",
-                    tag_text,
-                    full_node_text(&result[&tag]),
-                    full_node_text(&dd),
-                ),
-            };
-        } else {
-            result.insert(tag, dd);
-        }
-    }
-
-    Ok((result, unknown_tags))
-}
@@ -1,29 +1,77 @@
 #![allow(unused_must_use)]
-#![windows_subsystem = "windows"]
+// Release GUI builds get no console window on Windows; `attach_parent_console` gives the
+// subcommands back the one they were started from.
+#![cfg_attr(
+    all(not(debug_assertions), not(feature = "no_gui")),
+    windows_subsystem = "windows"
+)]
 
+mod cache;
+mod catalog;
+mod chapters;
+mod cli;
+mod duplicates;
+mod errors;
+mod export;
+mod filter;
+#[cfg(not(feature = "no_gui"))]
 mod frontend_iced;
 mod get_data;
+mod organize;
 mod serialization;
+mod sources;
 mod tags;
 mod utils;
+mod watch;
+mod write_back;
 
 use anyhow::Result;
+use clap::Parser;
+
+/// Attaches to the console of the parent process, so that the output of the command line
+/// interface shows up when the binary is built for the windows subsystem.
+#[cfg(all(windows, not(debug_assertions), not(feature = "no_gui")))]
+fn attach_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    // Fails when started from outside a console, e.g. from the explorer, which is fine.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
 
 fn main() -> Result<()> {
+    // Any argument means the command line interface, including `--help` and usage errors.
+    #[cfg(all(windows, not(debug_assertions), not(feature = "no_gui")))]
+    if std::env::args_os().len() > 1 {
+        attach_parent_console();
+    }
+
+    let cli = cli::Cli::parse();
+
     let mut clog = colog::default_builder();
+    clog.filter(None, cli.log_level());
+    clog.init();
 
-    if cfg!(debug_assertions) {
-        clog.filter(None, log::LevelFilter::Info);
-    } else {
-        clog.filter(None, log::LevelFilter::Warn);
+    if let Some(jobs) = cli.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
     }
-    clog.init();
 
-    #[cfg(not(feature = "no_gui"))]
-    frontend_iced::main()?;
+    match cli.command {
+        Some(command) => cli::run(command)?,
+        None => {
+            #[cfg(not(feature = "no_gui"))]
+            frontend_iced::main()?;
 
-    #[cfg(feature = "no_gui")]
-    get_data::generate_workbook("./res.xlsx", ["./ignore/"].iter()).unwrap();
+            #[cfg(feature = "no_gui")]
+            <cli::Cli as clap::CommandFactory>::command().print_help()?;
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,370 @@
+//! Moving or copying epubs into a directory layout built from their metadata.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::{anyhow, bail, Result as AnyResult};
+use log::{info, warn};
+use regex::Regex;
+
+use crate::{
+    serialization::FullFicInfo,
+    utils::{canonical_path, mkregex},
+};
+
+/// Fields that can be used in a template, as `{field}`.
+const TEMPLATE_FIELDS: &[&str] = &[
+    "title", "creator", "creators", "fandom", "series", "part", "rating", "language", "source",
+    "work_id",
+];
+
+/// Longest file or directory name written, in bytes, leaving room for collision suffixes.
+const MAX_COMPONENT_LENGTH: usize = 200;
+
+mkregex!(RE_TEMPLATE_FIELD, r"\{(\w+)\}");
+mkregex!(
+    RE_WINDOWS_RESERVED_NAME,
+    r"(?i)^(con|prn|aux|nul|com[0-9]|lpt[0-9])(\..*)?$"
+);
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct OrganizeOptions {
+    /// Directory the organized library is written to
+    #[arg(short, long)]
+    pub target: PathBuf,
+
+    /// Path of each epub relative to the target, made of `{title}`, `{creator}` (the first
+    /// one), `{creators}`, `{fandom}` (the first one), `{series}`, `{part}`, `{rating}`,
+    /// `{language}`, `{source}` and `{work_id}`. Missing values are left empty and empty
+    /// directory levels are dropped
+    #[arg(
+        long,
+        default_value = "{fandom}/{creator}/{series}/{part} - {title}.epub"
+    )]
+    pub template: String,
+
+    /// Copy the epubs instead of moving them
+    #[arg(long)]
+    pub copy: bool,
+
+    /// Only print what would be done
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Where to write the log used to undo the operation, defaults to a timestamped file in
+    /// the target directory
+    #[arg(long)]
+    pub undo_log: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileOperation {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub copied: bool,
+}
+
+/// Record of an organize run, replayed backwards by [`undo_organize`].
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct OrganizeLog {
+    pub target: PathBuf,
+    pub operations: Vec<FileOperation>,
+}
+
+fn validate_template(template: &str) -> AnyResult<()> {
+    for caps in RE_TEMPLATE_FIELD.captures_iter(template) {
+        if !TEMPLATE_FIELDS.contains(&&caps[1]) {
+            bail!(
+                "unknown field `{{{}}}` in template, expected one of: {}",
+                &caps[1],
+                TEMPLATE_FIELDS.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn template_field(fic_info: &FullFicInfo, field: &str) -> Option<String> {
+    let meta_info = &fic_info.meta_info;
+    let tags = fic_info.tags.as_ref().ok();
+    let first_series = tags.and_then(|tags| tags.series.first());
+    match field {
+        "title" => meta_info
+            .title
+            .clone()
+            .or_else(|| Some(meta_info.path_to_file.file_stem()?.to_string_lossy().into())),
+        "creator" => meta_info.creators.first().cloned(),
+        "creators" => Some(meta_info.creators.join(", ")),
+        "fandom" => tags?.fandoms.first().cloned(),
+        "series" => first_series.map(|series| series.name.clone()),
+        "part" => first_series?.part.map(|part| part.to_string()),
+        "rating" => tags?.rating.clone(),
+        "language" => tags?.language.clone(),
+        "source" => meta_info.source.clone(),
+        "work_id" => meta_info.work_id.map(|work_id| work_id.to_string()),
+        _ => None,
+    }
+}
+
+/// Makes `s` usable as a single file or directory name on common file systems.
+fn sanitize_component(s: &str) -> String {
+    let replaced: String = s
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let mut sanitized = replaced
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    if RE_WINDOWS_RESERVED_NAME.is_match(&sanitized) {
+        sanitized.insert(0, '_');
+    }
+    truncate_component(&sanitized)
+}
+
+/// Shortens a file name to [`MAX_COMPONENT_LENGTH`] bytes, keeping its extension.
+fn truncate_component(name: &str) -> String {
+    if name.len() <= MAX_COMPONENT_LENGTH {
+        return name.to_string();
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if extension.len() < 10 => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let mut end = MAX_COMPONENT_LENGTH - extension.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", stem[..end].trim_end(), extension)
+}
+
+/// Fills `template` with the fields of `fic_info` and returns the resulting path relative to
+/// the target directory. Separators left dangling by missing values (`" - title"`) are
+/// trimmed, and empty levels are dropped.
+pub fn render_template(template: &str, fic_info: &FullFicInfo) -> AnyResult<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in template.split('/') {
+        let rendered = RE_TEMPLATE_FIELD.replace_all(component, |caps: &regex::Captures| {
+            sanitize_component(&template_field(fic_info, &caps[1]).unwrap_or_default())
+        });
+        let trimmed = rendered
+            .trim_matches(|c: char| c.is_whitespace() || c == '-' || c == '_')
+            .trim_start_matches('.');
+        if trimmed.is_empty() {
+            continue;
+        }
+        path.push(sanitize_component(trimmed));
+    }
+    if path.file_name().is_none() {
+        bail!(
+            "template gives an empty path for `{}`",
+            fic_info.meta_info.path_to_file.display()
+        );
+    }
+    if path.extension().is_none_or(|extension| extension != "epub") {
+        path.as_mut_os_string().push(".epub");
+    }
+    Ok(path)
+}
+
+/// First variant of `path` (`name.epub`, `name (2).epub`, ...) that neither exists nor is
+/// already taken by another epub of this run.
+pub fn free_destination(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let is_free = |candidate: &Path| !candidate.exists() && !taken.contains(candidate);
+    if is_free(path) {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (2..)
+        .map(|i| path.with_file_name(format!("{} ({}){}", stem, i, extension)))
+        .find(|candidate| is_free(candidate))
+        .unwrap()
+}
+
+/// Plans where every fic goes, with absolute paths. Epubs already at their destination are
+/// left out.
+pub fn plan_operations(
+    fics: &[FullFicInfo],
+    options: &OrganizeOptions,
+) -> AnyResult<Vec<FileOperation>> {
+    validate_template(&options.template)?;
+    // Paths are compared and logged in canonical form, so that epubs already in place are
+    // recognised and the log can be replayed from any directory.
+    let target = canonical_path(&options.target);
+    let mut taken = HashSet::new();
+    let mut operations = vec![];
+    for fic_info in fics {
+        let from = canonical_path(&fic_info.meta_info.path_to_file);
+        let to = canonical_path(&target.join(render_template(&options.template, fic_info)?));
+        if to == from {
+            continue;
+        }
+        let to = free_destination(&to, &taken);
+        taken.insert(to.clone());
+        operations.push(FileOperation {
+            from,
+            to,
+            copied: options.copy,
+        });
+    }
+    Ok(operations)
+}
+
+/// Moves `from` to `to`, copying then removing when they are on different file systems.
+fn move_file(from: &Path, to: &Path) -> AnyResult<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+fn default_undo_log_path(target: &Path) -> PathBuf {
+    target.join(format!(
+        "organize-undo-{}.json",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ))
+}
+
+/// Moves or copies `fics` into the layout given by `options`, printing every operation.
+/// The operations done are written to the undo log even when one of them fails.
+pub fn organize(fics: &[FullFicInfo], options: &OrganizeOptions) -> AnyResult<()> {
+    let operations = plan_operations(fics, options)?;
+    for operation in &operations {
+        println!("{} -> {}", operation.from.display(), operation.to.display());
+    }
+    if options.dry_run || operations.is_empty() {
+        return Ok(());
+    }
+
+    let mut log = OrganizeLog {
+        target: canonical_path(&options.target),
+        operations: vec![],
+    };
+    let mut result = Ok(());
+    for operation in operations {
+        let done = (|| -> AnyResult<()> {
+            if let Some(parent) = operation.to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if operation.copied {
+                fs::copy(&operation.from, &operation.to)?;
+            } else {
+                move_file(&operation.from, &operation.to)?;
+            }
+            Ok(())
+        })();
+        if let Err(err) = done {
+            result = Err(anyhow!(
+                "cannot organize `{}`: {}",
+                operation.from.display(),
+                err
+            ));
+            break;
+        }
+        log.operations.push(operation);
+    }
+
+    let log_path = options
+        .undo_log
+        .clone()
+        .unwrap_or_else(|| default_undo_log_path(&options.target));
+    fs::create_dir_all(&options.target)?;
+    fs::write(&log_path, serde_json::to_string_pretty(&log)?)?;
+    info!(
+        "organized {} epubs, undo log written to `{}`",
+        log.operations.len(),
+        log_path.display()
+    );
+    result
+}
+
+/// Reverts the operations recorded in the undo log at `log_path`, last one first: copies are
+/// deleted and moved epubs go back to their original place. Directories left empty inside
+/// the target are removed.
+pub fn undo_organize<P: AsRef<Path>>(log_path: P) -> AnyResult<()> {
+    let log: OrganizeLog = serde_json::from_str(&fs::read_to_string(log_path)?)?;
+    for operation in log.operations.iter().rev() {
+        if !operation.to.exists() {
+            warn!("`{}` no longer exists, skipping", operation.to.display());
+            continue;
+        }
+        if operation.copied {
+            fs::remove_file(&operation.to)?;
+        } else {
+            if operation.from.exists() {
+                warn!(
+                    "`{}` exists again, leaving `{}` in place",
+                    operation.from.display(),
+                    operation.to.display()
+                );
+                continue;
+            }
+            if let Some(parent) = operation.from.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            move_file(&operation.to, &operation.from)?;
+        }
+        println!("{} <- {}", operation.from.display(), operation.to.display());
+
+        let mut dir = operation.to.parent();
+        while let Some(parent) =
+            dir.filter(|dir| dir.starts_with(&log.target) && *dir != log.target)
+        {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_absolute_paths_and_skips_epubs_in_place() {
+        let dir = std::env::temp_dir().join(format!("organize-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("In Place.epub"), "").unwrap();
+        fs::write(dir.join("sub/moved.epub"), "").unwrap();
+        let options = OrganizeOptions {
+            target: dir.join("sub/.."),
+            template: "{title}.epub".into(),
+            copy: false,
+            dry_run: true,
+            undo_log: None,
+        };
+        let operations = plan_operations(
+            &[
+                FullFicInfo::titled("In Place").at(dir.join("sub/../In Place.epub")),
+                FullFicInfo::titled("Moved").at(dir.join("sub/moved.epub")),
+            ],
+            &options,
+        );
+        let canonical_dir = dir.canonicalize().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = canonical_dir;
+        let operations = operations.unwrap();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].from, dir.join("sub/moved.epub"));
+        assert_eq!(operations[0].to, dir.join("Moved.epub"));
+    }
+}
@@ -1,108 +1,405 @@
 use std::{path::PathBuf, sync::LazyLock};
 
 use crate::{
-    tags::ParsedAO3Tags,
-    utils::{pub_static_with_lock, static_with_lock, vec_as_newlines},
+    chapters::ChapterInfo,
+    errors::{FicError, FicErrorKind},
+    tags::{AO3Stats, ChapterNotes, ParsedAO3Tags, SeriesMembership, TagKind},
+    utils::{pub_static_with_lock, static_with_lock},
 };
 use anyhow::bail;
+use itertools::Itertools;
 use log::warn;
-use rust_xlsxwriter::{Color, Format, Worksheet};
+use rust_xlsxwriter::{Color, Format, Formula, Url, Worksheet};
 use serde::Serialize;
 use serde_aux::prelude::serde_introspect;
+use serde_json::{Map, Value};
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FicMetaInfo {
     pub path_to_file: PathBuf,
     pub title: Option<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
+    /// Short name of the archive the epub was downloaded from, see `sources`
+    pub source: Option<String>,
+    pub work_id: Option<u64>,
+    pub work_url: Option<String>,
     pub creators: Vec<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
     pub publisher: Vec<String>,
     pub description: Option<String>,
+    /// Words counted in the chapter text, see `chapters::TextLength`
+    #[serde(default)]
+    pub local_words: Option<u64>,
+    #[serde(default)]
+    pub local_characters: Option<u64>,
+    #[serde(default)]
+    pub reading_minutes: Option<u64>,
+    /// Whether `local_words` is far from the word count declared by the archive
+    #[serde(default)]
+    pub word_count_mismatch: Option<bool>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FullFicInfo {
     pub meta_info: FicMetaInfo,
-    pub tags: Result<ParsedAO3Tags, String>,
+    pub tags: Result<ParsedAO3Tags, FicError>,
+    #[serde(default)]
+    pub chapters: Vec<ChapterInfo>,
+}
+
+/// Flat view of a [`FullFicInfo`] used by the JSON exports: the meta info and tag fields
+/// side by side, keeping lists as arrays, plus `error_kind` and `error_message` when the epub
+/// could not be fully explored.
+#[derive(Debug, serde::Serialize)]
+pub struct FicJsonRecord<'a> {
+    #[serde(flatten)]
+    pub meta_info: &'a FicMetaInfo,
+    #[serde(flatten)]
+    pub tags: Option<&'a ParsedAO3Tags>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<FicErrorKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub chapters: &'a [ChapterInfo],
+}
+
+impl FullFicInfo {
+    pub fn as_json_record(&self) -> FicJsonRecord<'_> {
+        FicJsonRecord {
+            meta_info: &self.meta_info,
+            tags: self.tags.as_ref().ok(),
+            error_kind: self.tags.as_ref().err().map(|err| err.kind),
+            error_message: self.tags.as_ref().err().map(|err| err.message.as_str()),
+            chapters: &self.chapters,
+        }
+    }
+}
+
+/// Builds the works used by the tests of every module: a title and parsed tags that are
+/// empty until set.
+#[cfg(test)]
+impl FullFicInfo {
+    pub fn titled(title: &str) -> Self {
+        FullFicInfo {
+            meta_info: FicMetaInfo {
+                title: Some(title.into()),
+                ..Default::default()
+            },
+            tags: Ok(ParsedAO3Tags::default()),
+            chapters: vec![],
+        }
+    }
+
+    pub fn at(mut self, path: impl Into<PathBuf>) -> Self {
+        self.meta_info.path_to_file = path.into();
+        self
+    }
+
+    pub fn by(mut self, creators: &[&str]) -> Self {
+        self.meta_info.creators = creators.iter().map(|&creator| creator.into()).collect();
+        self
+    }
+
+    pub fn without_tags(mut self) -> Self {
+        self.tags = Err(FicError::new(FicErrorKind::MissingTags, "no tags"));
+        self
+    }
+
+    pub fn rated(mut self, rating: &str) -> Self {
+        self.tags_mut().rating = Some(rating.into());
+        self
+    }
+
+    pub fn in_fandoms(mut self, fandoms: &[&str]) -> Self {
+        self.tags_mut().fandoms = fandoms.iter().map(|&fandom| fandom.into()).collect();
+        self
+    }
+
+    /// Sets the relationships along with their parsed form, as exploring the epub does.
+    pub fn with_relationships(mut self, relationships: &[&str]) -> Self {
+        let tags = self.tags_mut();
+        tags.relationships = relationships.iter().map(|&tag| tag.into()).collect();
+        tags.parsed_relationships = relationships
+            .iter()
+            .map(|tag| crate::tags::Relationship::parse(tag))
+            .collect();
+        self
+    }
+
+    pub fn with_words(mut self, words: u64) -> Self {
+        self.stats_mut().words = Some(words);
+        self
+    }
+
+    pub fn published(mut self, date: chrono::NaiveDate) -> Self {
+        self.stats_mut().published = Some(date);
+        self
+    }
+
+    fn tags_mut(&mut self) -> &mut ParsedAO3Tags {
+        self.tags.as_mut().expect("the work has no tags")
+    }
+
+    fn stats_mut(&mut self) -> &mut AO3Stats {
+        self.tags_mut()
+            .parsed_stats
+            .get_or_insert_with(Default::default)
+    }
+}
+
+/// A field holding a nested struct (or a list of structs) whose own fields are spread over
+/// several table columns, named `<prefix><subfield>`.
+struct NestedColumns {
+    field: &'static str,
+    prefix: &'static str,
+    subfields: &'static [&'static str],
+}
+
+static_with_lock!(
+    PARSEDAO3TAGS_NESTED_COLUMNS,
+    Vec<NestedColumns>,
+    vec![
+        NestedColumns {
+            field: "series",
+            prefix: "series_",
+            subfields: serde_introspect::<SeriesMembership>(),
+        },
+        NestedColumns {
+            field: "parsed_stats",
+            prefix: "",
+            subfields: serde_introspect::<AO3Stats>(),
+        },
+        // The members are already in `relationships`, only their kind gets its own column.
+        NestedColumns {
+            field: "parsed_relationships",
+            prefix: "relationship_",
+            subfields: &["kind"],
+        },
+        NestedColumns {
+            field: "chapter_notes",
+            prefix: "chapter_",
+            subfields: serde_introspect::<ChapterNotes>(),
+        },
+    ]
+);
+
+fn table_columns(field_names: &[&str], nested_columns: &[NestedColumns]) -> Vec<String> {
+    field_names
+        .iter()
+        .flat_map(|&field_name| {
+            match nested_columns
+                .iter()
+                .find(|nested| nested.field == field_name)
+            {
+                Some(nested) => nested
+                    .subfields
+                    .iter()
+                    .map(|subfield| format!("{}{}", nested.prefix, subfield))
+                    .collect(),
+                None => vec![field_name.to_string()],
+            }
+        })
+        .collect()
 }
 
 static_with_lock!(
     FICMETAINFO_FIELD_NAMES,
-    Vec<&str>,
-    serde_introspect::<FicMetaInfo>().into()
+    Vec<String>,
+    table_columns(serde_introspect::<FicMetaInfo>(), &[])
 );
+/// Tag fields left out of the table, which are written on a worksheet of their own.
+static PARSEDAO3TAGS_SKIPPED_FIELDS: &[&str] = &["tag_links"];
+
 static_with_lock!(
     PARSEDAO3TAGS_FIELD_NAMES,
-    Vec<&str>,
-    serde_introspect::<ParsedAO3Tags>().into()
+    Vec<String>,
+    table_columns(
+        &serde_introspect::<ParsedAO3Tags>()
+            .iter()
+            .copied()
+            .filter(|field_name| !PARSEDAO3TAGS_SKIPPED_FIELDS.contains(field_name))
+            .collect::<Vec<_>>(),
+        &PARSEDAO3TAGS_NESTED_COLUMNS
+    )
 );
+/// Columns describing why an epub could not be fully explored, between the meta info and
+/// the tags.
+static ERROR_COLUMNS: [&str; 2] = ["error_kind", "error_message"];
+
 pub_static_with_lock!(
     ALL_TABLE_COLUMNS,
-    Vec<&str>,
-    [&FICMETAINFO_FIELD_NAMES[..], &PARSEDAO3TAGS_FIELD_NAMES[..]].concat()
+    Vec<String>,
+    [
+        &FICMETAINFO_FIELD_NAMES[..],
+        &ERROR_COLUMNS.map(String::from)[..],
+        &PARSEDAO3TAGS_FIELD_NAMES[..],
+    ]
+    .concat()
 );
 
-fn serialize_struct_fields_to_vec_of_string<S: Serialize>(
+/// Columns holding an URL, written as clickable hyperlinks in xlsx.
+static HYPERLINK_COLUMNS: &[&str] = &["work_url"];
+
+/// Excel refuses to open worksheets with more hyperlinks than about 65,530; URLs past that
+/// are written as plain text.
+const MAX_HYPERLINKS_PER_WORKSHEET: u32 = 65_530;
+
+/// Separator used to join multiple values (fandoms, characters, ...) inside one xlsx cell.
+pub const XLSX_MULTI_VALUE_SEPARATOR: &str = "\n";
+
+/// Turns a serialized field into a single cell value: `null`, a number or a string, joining
+/// the elements of lists with `multi_value_separator`. Single-element lists are unwrapped so
+/// that e.g. the part number of a work in only one series stays a number. Maps become
+/// `key <value>` entries.
+fn flatten_value(value: &Value, multi_value_separator: &str) -> Value {
+    match value {
+        Value::Array(values) if values.len() == 1 => {
+            flatten_value(&values[0], multi_value_separator)
+        }
+        Value::Array(values) => Value::String(
+            values
+                .iter()
+                .map(|value| cell_to_string(&flatten_value(value, multi_value_separator)))
+                .join(multi_value_separator),
+        ),
+        Value::Object(map) if map.is_empty() => Value::Null,
+        Value::Object(map) => Value::String(
+            map.iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{} <{}>",
+                        key,
+                        cell_to_string(&flatten_value(value, multi_value_separator))
+                    )
+                })
+                .join(multi_value_separator),
+        ),
+        scalar => scalar.clone(),
+    }
+}
+
+pub fn cell_to_string(cell: &Value) -> String {
+    match cell {
+        Value::Null => "".into(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Serializes `strct` and spreads the fields listed in `nested_columns` over their own
+/// columns; lists of structs become one list per subfield.
+fn flatten_struct_fields<S: Serialize>(
     strct: &S,
-    field_names: &'static [&str],
-) -> anyhow::Result<Vec<String>> {
-    let ser = serde_json::value::to_value(strct)?;
-    match ser {
-        serde_json::Value::Object(map) => Ok(field_names
+    nested_columns: &[NestedColumns],
+) -> anyhow::Result<Map<String, Value>> {
+    let Value::Object(map) = serde_json::value::to_value(strct)? else {
+        bail!("bad serde struct: {}", std::any::type_name::<S>());
+    };
+    let mut result = Map::new();
+    for (field_name, value) in map {
+        let Some(nested) = nested_columns
             .iter()
-            .map(|&field_name| {
-                let field_value = map[field_name].to_owned();
-                let parsed_value = serde_json::from_value::<Option<String>>(field_value.clone());
-                parsed_value
-                    .unwrap_or_else(|err| {
-                        warn!("cannot convert serialized data to string:\n\t{}", err);
-                        Some("".into())
-                    })
-                    .unwrap_or("".into())
-            })
-            .collect()),
-        _ => bail!("bad serde struct: {:?}", ser),
+            .find(|nested| nested.field == field_name)
+        else {
+            result.insert(field_name, value);
+            continue;
+        };
+        for &subfield in nested.subfields {
+            let subvalue = match &value {
+                Value::Object(submap) => submap.get(subfield).cloned().unwrap_or_default(),
+                Value::Array(items) => Value::Array(
+                    items
+                        .iter()
+                        .map(|item| item.get(subfield).cloned().unwrap_or_default())
+                        .collect(),
+                ),
+                _ => Value::Null,
+            };
+            result.insert(format!("{}{}", nested.prefix, subfield), subvalue);
+        }
     }
+    Ok(result)
+}
+
+fn serialize_struct_fields_to_cells<S: Serialize>(
+    strct: &S,
+    field_names: &[String],
+    nested_columns: &[NestedColumns],
+    multi_value_separator: &str,
+) -> anyhow::Result<Vec<Value>> {
+    let map = flatten_struct_fields(strct, nested_columns)?;
+    Ok(field_names
+        .iter()
+        .map(|field_name| {
+            flatten_value(
+                map.get(field_name).unwrap_or(&Value::Null),
+                multi_value_separator,
+            )
+        })
+        .collect())
+}
+
+/// Flattens `fic_info` into one cell per column of [`ALL_TABLE_COLUMNS`], joining
+/// multi-valued fields with `multi_value_separator`. The error columns are filled when the
+/// tags could not be parsed, the tag columns otherwise.
+pub fn fic_to_table_row(
+    fic_info: &FullFicInfo,
+    multi_value_separator: &str,
+) -> anyhow::Result<Vec<Value>> {
+    let mut row = serialize_struct_fields_to_cells(
+        &fic_info.meta_info,
+        &FICMETAINFO_FIELD_NAMES,
+        &[],
+        multi_value_separator,
+    )?;
+    match &fic_info.tags {
+        Ok(tags) => {
+            row.extend(ERROR_COLUMNS.map(|_| Value::Null));
+            row.extend(serialize_struct_fields_to_cells(
+                tags,
+                &PARSEDAO3TAGS_FIELD_NAMES,
+                &PARSEDAO3TAGS_NESTED_COLUMNS,
+                multi_value_separator,
+            )?)
+        }
+        Err(err) => {
+            row.push(Value::String(err.kind.name().into()));
+            row.push(Value::String(err.message.clone()));
+            row.resize(ALL_TABLE_COLUMNS.len(), Value::Null);
+        }
+    }
+    Ok(row)
 }
 
 pub fn write_headers(worksheet: &mut Worksheet) -> anyhow::Result<()> {
-    let fields_names = [
-        serde_introspect::<FicMetaInfo>(),
-        serde_introspect::<ParsedAO3Tags>(),
-    ]
-    .concat();
-    worksheet.write_row_with_format(0, 0, fields_names, &Format::new().set_bold())?;
+    worksheet.write_row_with_format(0, 0, ALL_TABLE_COLUMNS.iter(), &Format::new().set_bold())?;
     Ok(())
 }
 
-pub fn write_fic_to_worksheet_row(
-    worksheet: &mut Worksheet,
-    row: usize,
-    fic_info: &FullFicInfo,
-) -> () {
+pub fn write_fic_to_worksheet_row(worksheet: &mut Worksheet, row: usize, fic_info: &FullFicInfo) {
     let row = row.try_into().unwrap_or(u32::MAX);
     let mut perform_operation = || -> anyhow::Result<()> {
-        let vec_of_meta_info_fields = serialize_struct_fields_to_vec_of_string(
-            &fic_info.meta_info,
-            &FICMETAINFO_FIELD_NAMES,
-        )?;
-        let n_info_fields_cols: u16 = vec_of_meta_info_fields.len().try_into().unwrap();
-        worksheet.write_row(row, 0, vec_of_meta_info_fields)?;
-
-        match &fic_info.tags {
-            Ok(tags) => {
-                let vec_of_tags_fields =
-                    serialize_struct_fields_to_vec_of_string(&tags, &PARSEDAO3TAGS_FIELD_NAMES)?;
-                worksheet.write_row(row, n_info_fields_cols, vec_of_tags_fields)?;
-            }
-            Err(err) => {
-                worksheet.write_string_with_format(
-                    row,
-                    n_info_fields_cols,
-                    err,
-                    &Format::new().set_font_color(Color::Red),
-                )?;
+        let cells = fic_to_table_row(fic_info, XLSX_MULTI_VALUE_SEPARATOR)?;
+        for (col, (cell, column_name)) in cells.iter().zip(ALL_TABLE_COLUMNS.iter()).enumerate() {
+            let col = col.try_into()?;
+            match cell {
+                Value::Null => continue,
+                Value::Number(number) => {
+                    worksheet.write_number(row, col, number.as_f64().unwrap_or(f64::NAN))?
+                }
+                Value::String(url)
+                    if HYPERLINK_COLUMNS.contains(&column_name.as_str())
+                        && row <= MAX_HYPERLINKS_PER_WORKSHEET =>
+                {
+                    worksheet.write_url(row, col, Url::new(url))?
+                }
+                other => worksheet.write_string(row, col, cell_to_string(other))?,
+            };
+        }
+
+        if fic_info.tags.is_err() {
+            let n_info_fields_cols: u16 = FICMETAINFO_FIELD_NAMES.len().try_into()?;
+            for col in n_info_fields_cols..n_info_fields_cols + ERROR_COLUMNS.len() as u16 {
+                worksheet.set_cell_format(row, col, &Format::new().set_font_color(Color::Red))?;
             }
         }
 
@@ -118,3 +415,221 @@ pub fn write_fic_to_worksheet_row(
         );
     });
 }
+
+const TAG_LINKS_COLUMNS: [&str; 5] = ["path_to_file", "title", "kind", "tag", "url"];
+
+/// Number of rows of an xlsx worksheet, the header included.
+const MAX_ROWS_PER_WORKSHEET: u32 = 1_048_576;
+
+/// Longest string literal Excel accepts inside a formula.
+const MAX_FORMULA_STRING_LENGTH: usize = 255;
+
+/// `=HYPERLINK(url, text)` formula, which unlike hyperlinks proper is not limited in number
+/// per worksheet; `None` when `url` or `text` is too long for a formula.
+fn hyperlink_formula(url: &str, text: &str) -> Option<Formula> {
+    let quote = |s: &str| {
+        (s.chars().count() <= MAX_FORMULA_STRING_LENGTH)
+            .then(|| format!("\"{}\"", s.replace('"', "\"\"")))
+    };
+    Some(Formula::new(format!("=HYPERLINK({}, {})", quote(url)?, quote(text)?)).set_result(text))
+}
+
+/// Writes one row per tag of every work, the tag name linking to its archive page when the
+/// epub recorded it. Rows past the worksheet limit are left out with a warning.
+pub fn write_tag_links_worksheet(
+    worksheet: &mut Worksheet,
+    fics: &[FullFicInfo],
+) -> anyhow::Result<()> {
+    worksheet.set_name("tags")?;
+    worksheet.write_row_with_format(0, 0, TAG_LINKS_COLUMNS, &Format::new().set_bold())?;
+
+    let link_format = Format::new().set_hyperlink();
+    let rows = fics
+        .iter()
+        .filter_map(|fic_info| Some((fic_info, fic_info.tags.as_ref().ok()?)))
+        .flat_map(|(fic_info, tags)| {
+            tags.all_tags().into_iter().flat_map(move |(kind, names)| {
+                names
+                    .iter()
+                    .map(move |name| (fic_info, kind, name, tags.tag_url(kind, name)))
+            })
+        });
+    let mut n_skipped = 0;
+    for (row, (fic_info, kind, name, url)) in (1..).zip(rows) {
+        if row >= MAX_ROWS_PER_WORKSHEET {
+            n_skipped += 1;
+            continue;
+        }
+        worksheet.write_string(row, 0, fic_info.meta_info.path_to_file.to_string_lossy())?;
+        worksheet.write_string(row, 1, fic_info.meta_info.title.as_deref().unwrap_or(""))?;
+        worksheet.write_string(row, 2, kind.name())?;
+        match url.and_then(|url| hyperlink_formula(url, name)) {
+            Some(formula) => worksheet.write_formula_with_format(row, 3, formula, &link_format)?,
+            None => worksheet.write_string(row, 3, name)?,
+        };
+        if let Some(url) = url {
+            worksheet.write_string(row, 4, url)?;
+        }
+    }
+    if n_skipped > 0 {
+        warn!(
+            "too many tags for one worksheet, the last {} tags are left out of it",
+            n_skipped
+        );
+    }
+    worksheet.autofit();
+    Ok(())
+}
+
+/// Field whose values are counted across the library, on a worksheet of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrequencyKind {
+    Tag(TagKind),
+    Creator,
+    Language,
+}
+
+pub const FREQUENCY_KINDS: [FrequencyKind; 7] = [
+    FrequencyKind::Tag(TagKind::Fandom),
+    FrequencyKind::Tag(TagKind::Relationship),
+    FrequencyKind::Tag(TagKind::Character),
+    FrequencyKind::Tag(TagKind::AdditionalTag),
+    FrequencyKind::Creator,
+    FrequencyKind::Tag(TagKind::Rating),
+    FrequencyKind::Language,
+];
+
+impl FrequencyKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tag(kind) => kind.name(),
+            Self::Creator => "creator",
+            Self::Language => "language",
+        }
+    }
+
+    fn values(self, fic_info: &FullFicInfo) -> Vec<&str> {
+        let tags = fic_info.tags.as_ref().ok();
+        let values: &[String] = match self {
+            Self::Tag(kind) => tags.map_or(&[], |tags| tags.tags_of_kind(kind)),
+            Self::Creator => &fic_info.meta_info.creators,
+            Self::Language => tags.map_or(&[], |tags| tags.language.as_slice()),
+        };
+        values.iter().map(|value| value.trim()).unique().collect()
+    }
+
+    /// Number of works that can have values of this kind: creators come from the OPF of
+    /// every epub, the other kinds only from the parsed tags.
+    pub fn n_works(self, fics: &[FullFicInfo]) -> usize {
+        match self {
+            Self::Creator => fics.len(),
+            Self::Tag(_) | Self::Language => {
+                fics.iter().filter(|fic_info| fic_info.tags.is_ok()).count()
+            }
+        }
+    }
+}
+
+/// Number of works having each value of `kind`, the most frequent first and ties sorted by
+/// name.
+pub fn tag_frequencies(fics: &[FullFicInfo], kind: FrequencyKind) -> Vec<(&str, usize)> {
+    fics.iter()
+        .flat_map(|fic_info| kind.values(fic_info))
+        .counts()
+        .into_iter()
+        .sorted_by(|(a_name, a_count), (b_name, b_count)| {
+            b_count.cmp(a_count).then(a_name.cmp(b_name))
+        })
+        .collect()
+}
+
+/// Writes `frequencies` with the share of the `n_works` works that can have values of `kind`.
+pub fn write_tag_frequency_worksheet(
+    worksheet: &mut Worksheet,
+    kind: FrequencyKind,
+    frequencies: &[(&str, usize)],
+    n_works: usize,
+) -> anyhow::Result<()> {
+    worksheet.set_name(format!("{} frequency", kind.name()))?;
+    worksheet.write_row_with_format(
+        0,
+        0,
+        [kind.name(), "works", "share"],
+        &Format::new().set_bold(),
+    )?;
+
+    let percentage = Format::new().set_num_format("0.0%");
+    for (row, (name, count)) in (1..).zip(frequencies) {
+        worksheet.write_string(row, 0, *name)?;
+        worksheet.write_number(row, 1, *count as f64)?;
+        worksheet.write_number_with_format(
+            row,
+            2,
+            *count as f64 / n_works.max(1) as f64,
+            &percentage,
+        )?;
+    }
+    worksheet.autofit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequencies_sorted_by_count_then_name() {
+        let fics = [
+            FullFicInfo::titled("A")
+                .by(&["b"])
+                .in_fandoms(&["Zelda", "Avatar"]),
+            FullFicInfo::titled("B")
+                .by(&["a"])
+                .in_fandoms(&["Zelda", "Bleach", " Avatar "]),
+            FullFicInfo::titled("C")
+                .by(&["b"])
+                .in_fandoms(&["Zelda", "Avatar", "Avatar"]),
+            FullFicInfo::titled("D").by(&["c"]).without_tags(),
+        ];
+        assert_eq!(
+            tag_frequencies(&fics, FrequencyKind::Tag(TagKind::Fandom)),
+            [("Avatar", 3), ("Zelda", 3), ("Bleach", 1)]
+        );
+        assert_eq!(
+            tag_frequencies(&fics, FrequencyKind::Creator),
+            [("b", 2), ("a", 1), ("c", 1)]
+        );
+    }
+
+    #[test]
+    fn shares_count_only_works_that_can_have_the_values() {
+        let fics = [
+            FullFicInfo::titled("A").by(&["a"]).in_fandoms(&["Zelda"]),
+            FullFicInfo::titled("B").by(&["b"]).without_tags(),
+        ];
+        assert_eq!(FrequencyKind::Tag(TagKind::Fandom).n_works(&fics), 1);
+        assert_eq!(FrequencyKind::Language.n_works(&fics), 1);
+        assert_eq!(FrequencyKind::Creator.n_works(&fics), 2);
+    }
+
+    #[test]
+    fn relationship_kinds_line_up_with_relationships() {
+        let fic_info =
+            FullFicInfo::titled("A").with_relationships(&["Original Character", "A & B", "C/D"]);
+        let row = fic_to_table_row(&fic_info, " | ").unwrap();
+        let cell = |column: &str| {
+            let index = ALL_TABLE_COLUMNS.iter().position(|name| name == column);
+            cell_to_string(&row[index.unwrap()])
+        };
+        assert_eq!(cell("relationships"), "Original Character | A & B | C/D");
+        assert_eq!(cell("relationship_kind"), " | platonic | romantic");
+    }
+
+    #[test]
+    fn hyperlink_formulas_only_for_short_strings() {
+        let long = "a".repeat(MAX_FORMULA_STRING_LENGTH + 1);
+        assert!(hyperlink_formula("https://archiveofourown.org/tags/A", "A \"B\"").is_some());
+        assert!(hyperlink_formula(&long, "A").is_none());
+        assert!(hyperlink_formula("https://archiveofourown.org/tags/A", &long).is_none());
+    }
+}
@@ -0,0 +1,326 @@
+//! Parsers for the epubs produced by the different fanfiction archives.
+//!
+//! Each archive lays out the work tags differently; [`SOURCE_PARSERS`] lists the supported
+//! ones, and adding an archive only requires implementing [`SourceParser`] and registering
+//! it there.
+
+mod ao3;
+mod fanficfare;
+mod ficbook;
+
+use anyhow::Result as AnyResult;
+use itertools::Itertools;
+use log::{debug, info};
+use rbook::{read::ContentType, Epub};
+use roxmltree::{Document, Node};
+
+use crate::{
+    errors::{FicError, FicErrorKind},
+    tags::ParsedAO3Tags,
+    utils::node_inner_text,
+};
+
+pub trait SourceParser: Sync {
+    /// Short name of the archive, stored in `FicMetaInfo::source`.
+    fn name(&self) -> &'static str;
+
+    /// Whether `epub` looks like it was downloaded from this archive.
+    fn detect(&self, epub: &Epub, spine: &Spine) -> bool;
+
+    /// Identifier of the work on the archive, if the epub records it.
+    fn work_id(&self, epub: &Epub, spine: &Spine) -> Option<u64>;
+
+    /// Canonical URL of the work with the given identifier, on the site `epub` comes from
+    /// for parsers covering several sites.
+    fn work_url(&self, epub: &Epub, work_id: u64) -> String;
+
+    /// Parses the tags, looking for them in the spine documents covered by `search`.
+    fn parse_tags(
+        &self,
+        epub: &Epub,
+        spine: &Spine,
+        search: SpineSearch,
+    ) -> AnyResult<ParsedAO3Tags>;
+}
+
+/// Which spine documents are searched for the tag block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SpineSearch {
+    FirstDocuments(usize),
+    WholeSpine,
+}
+
+impl Default for SpineSearch {
+    fn default() -> Self {
+        Self::FirstDocuments(5)
+    }
+}
+
+impl SpineSearch {
+    /// Whether this search goes through every document `other` does.
+    pub fn covers(self, other: Self) -> bool {
+        match (self, other) {
+            (Self::WholeSpine, _) => true,
+            (Self::FirstDocuments(_), Self::WholeSpine) => false,
+            (Self::FirstDocuments(n), Self::FirstDocuments(m)) => n >= m,
+        }
+    }
+
+    fn page_count(self, spine_length: usize) -> usize {
+        match self {
+            Self::FirstDocuments(n) => n.min(spine_length),
+            Self::WholeSpine => spine_length,
+        }
+    }
+}
+
+/// FanFicFare goes first: it also repackages AO3 works, but with its own title page.
+pub static SOURCE_PARSERS: &[&dyn SourceParser] = &[
+    &fanficfare::FanFicFareParser,
+    &ficbook::FicbookParser,
+    &ao3::AO3Parser,
+];
+
+/// Parses the tags with the parser of the archive `epub` comes from. When no archive is
+/// recognised, every parser is tried in turn and the first one that succeeds is used.
+pub fn parse_tags_with_detected_source(
+    epub: &Epub,
+    spine: &Spine,
+    search: SpineSearch,
+) -> (Option<&'static dyn SourceParser>, AnyResult<ParsedAO3Tags>) {
+    if let Some(&source) = SOURCE_PARSERS
+        .iter()
+        .find(|source| source.detect(epub, spine))
+    {
+        return (Some(source), source.parse_tags(epub, spine, search));
+    }
+
+    info!("could not detect the source archive, trying every parser");
+    // A parser not finding its own tag block is expected here; any other failure is more
+    // telling and is reported instead.
+    let mut first_error = None;
+    for &source in SOURCE_PARSERS {
+        match source.parse_tags(epub, spine, search) {
+            Ok(tags) => return (Some(source), Ok(tags)),
+            Err(err) => {
+                let is_missing_tags = err
+                    .downcast_ref::<FicError>()
+                    .is_some_and(|err| err.kind == FicErrorKind::MissingTags);
+                if !is_missing_tags {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+    }
+    (
+        None,
+        Err(first_error.unwrap_or_else(|| {
+            FicError::new(
+                FicErrorKind::MissingTags,
+                format!(
+                    "cannot find the tags of any supported archive ({})",
+                    SOURCE_PARSERS.iter().map(|source| source.name()).join(", ")
+                ),
+            )
+            .into()
+        })),
+    )
+}
+
+/// Text of the spine documents of an epub, read once for all the parsers and the chapter
+/// listing. Documents that cannot be read keep the error.
+pub struct SpineTexts(Vec<(String, Result<String, FicError>)>);
+
+impl SpineTexts {
+    pub fn read(epub: &Epub) -> Self {
+        let reader = epub.reader();
+        SpineTexts(
+            (0..reader.page_count())
+                .map(|page_index| match reader.fetch_page(page_index) {
+                    Some(Ok(content)) => (
+                        content
+                            .get_content(ContentType::Path)
+                            .unwrap_or_default()
+                            .to_string(),
+                        Ok(content.as_lossy_str().into_owned()),
+                    ),
+                    Some(Err(err)) => (
+                        String::new(),
+                        Err(FicError::new(FicErrorKind::PageFetch, err.to_string())),
+                    ),
+                    None => (
+                        String::new(),
+                        Err(FicError::new(
+                            FicErrorKind::PageFetch,
+                            format!(
+                                "could not match page {} of document due to lack of such",
+                                page_index
+                            ),
+                        )),
+                    ),
+                })
+                .collect(),
+        )
+    }
+
+    /// Parses every document that could be read.
+    pub fn parse(&self) -> Spine<'_> {
+        Spine {
+            documents: self
+                .0
+                .iter()
+                .map(|(path, text)| SpineDocument {
+                    path,
+                    text: text.as_ref().ok().map(String::as_str),
+                    doc: match text {
+                        Ok(text) => parse_xhtml(text),
+                        Err(err) => Err(err.clone()),
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The parsed spine documents of an epub, in reading order.
+pub struct Spine<'a> {
+    pub documents: Vec<SpineDocument<'a>>,
+}
+
+pub struct SpineDocument<'a> {
+    /// Path of the document in the archive
+    pub path: &'a str,
+    pub text: Option<&'a str>,
+    pub doc: Result<Document<'a>, FicError>,
+}
+
+impl Spine<'_> {
+    /// Text of the spine document with the given index, if it could be read.
+    pub fn text(&self, page_index: usize) -> Option<&str> {
+        self.documents.get(page_index)?.text
+    }
+
+    /// The documents that could be read and parsed.
+    pub fn parsed_documents(&self) -> impl Iterator<Item = &Document<'_>> {
+        self.documents
+            .iter()
+            .filter_map(|document| document.doc.as_ref().ok())
+    }
+}
+
+/// Goes through the spine documents covered by `search` in order, and returns the first
+/// value `find` gets out of one of them along with the path of that document.
+///
+/// Documents that could not be read or parsed are skipped; if nothing is found, the first
+/// such failure is returned as the error. Errors from `find` itself are returned right away.
+pub(crate) fn find_in_spine<T>(
+    spine: &Spine,
+    search: SpineSearch,
+    mut find: impl FnMut(&Document) -> AnyResult<Option<T>>,
+) -> AnyResult<Option<(T, String)>> {
+    let mut first_error = None;
+    for (page_index, document) in spine
+        .documents
+        .iter()
+        .enumerate()
+        .take(search.page_count(spine.documents.len()))
+    {
+        let doc = match &document.doc {
+            Ok(doc) => doc,
+            Err(err) => {
+                first_error.get_or_insert_with(|| err.clone());
+                continue;
+            }
+        };
+        if let Some(found) = find(doc)? {
+            debug!(
+                "found the tags in spine document {} `{}`",
+                page_index, document.path
+            );
+            return Ok(Some((found, document.path.to_string())));
+        }
+    }
+    match first_error {
+        Some(err) => Err(err.into()),
+        None => Ok(None),
+    }
+}
+
+fn parse_xhtml(content: &str) -> Result<Document<'_>, FicError> {
+    Document::parse_with_options(
+        content,
+        roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        },
+    )
+    .map_err(|err| {
+        FicError::new(
+            FicErrorKind::XmlParse,
+            format!("error during parsing: {}", err),
+        )
+    })
+}
+
+/// Collects `label: value` pairs laid out as `<b>Label:</b> value<br/>`, as done by the title
+/// pages of several archives. The value runs until the next `<br/>` or `<b>`.
+pub(crate) fn labelled_values(doc: &Document) -> Vec<(String, String)> {
+    doc.root()
+        .descendants()
+        .filter(|elt| elt.has_tag_name("b") || elt.has_tag_name("strong"))
+        .filter_map(|label| {
+            let label_text = node_inner_text(&label);
+            let label_text = label_text.trim().strip_suffix(':')?.trim();
+            let value = label
+                .next_siblings()
+                .skip(1)
+                .take_while(|elt| {
+                    !(elt.has_tag_name("br") || elt.has_tag_name("b") || elt.has_tag_name("strong"))
+                })
+                .map(|elt: Node| node_inner_text(&elt))
+                .collect::<Vec<_>>()
+                .join(" ");
+            Some((label_text.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// AO3 introduces the summary and every note with a `<p>` label (`Summary`, `Chapter End
+/// Notes`, ...) followed by a `<blockquote class="userstuff">`. Returns the lowercased labels
+/// with the text of their blocks, one line per paragraph.
+pub(crate) fn labelled_blocks(doc: &Document) -> Vec<(String, String)> {
+    doc.descendants()
+        .filter(|elt| elt.has_tag_name("blockquote"))
+        .filter_map(|block| {
+            let label = node_inner_text(&block.prev_sibling_element()?).to_lowercase();
+            let paragraphs = block
+                .children()
+                .filter(|elt| elt.has_tag_name("p"))
+                .map(|paragraph| node_inner_text(&paragraph))
+                .filter(|text| !text.is_empty())
+                .join("\n");
+            let text = if paragraphs.is_empty() {
+                node_inner_text(&block)
+            } else {
+                paragraphs
+            };
+            Some((label.trim_end_matches(':').trim().to_string(), text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wider_searches_cover_narrower_ones() {
+        use SpineSearch::*;
+        assert!(WholeSpine.covers(WholeSpine));
+        assert!(WholeSpine.covers(FirstDocuments(100)));
+        assert!(FirstDocuments(5).covers(FirstDocuments(5)));
+        assert!(FirstDocuments(5).covers(FirstDocuments(2)));
+        assert!(!FirstDocuments(2).covers(FirstDocuments(5)));
+        assert!(!FirstDocuments(100).covers(WholeSpine));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::LazyLock,
+};
+
+use anyhow::{bail, Result as AnyResult};
+use itertools::Itertools;
+use log::warn;
+use rbook::Epub;
+use regex::Regex;
+use roxmltree::Node;
+
+use super::{find_in_spine, labelled_blocks, SourceParser, Spine, SpineSearch};
+use crate::{
+    errors::{FicError, FicErrorKind},
+    tags::{AO3Tag, ChapterNotes, ParsedAO3Tags},
+    utils::{full_node_text, mkregex, node_inner_text},
+};
+
+/// Epubs downloaded from the Archive of Our Own, whose first page holds the work tags as a
+/// `<dl class="tags">` list.
+pub struct AO3Parser;
+
+impl SourceParser for AO3Parser {
+    fn name(&self) -> &'static str {
+        "ao3"
+    }
+
+    fn detect(&self, epub: &Epub, spine: &Spine) -> bool {
+        epub.metadata()
+            .publisher()
+            .iter()
+            .any(|elt| elt.value().contains("Archive of Our Own"))
+            || self.work_id(epub, spine).is_some()
+    }
+
+    /// Looks for the AO3 work URL in the OPF identifiers and sources, then in the preface
+    /// ("Posted originally on the Archive of Our Own at ...").
+    fn work_id(&self, epub: &Epub, spine: &Spine) -> Option<u64> {
+        let find_in = |s: &str| {
+            RE_AO3_WORK_URL
+                .captures(s)
+                .and_then(|caps| caps[1].parse().ok())
+        };
+        let metadata = epub.metadata();
+        metadata
+            .get("identifier")
+            .into_iter()
+            .chain(metadata.get("source"))
+            .find_map(|elt| find_in(elt.value()))
+            .or_else(|| find_in(spine.text(0)?))
+    }
+
+    fn work_url(&self, _epub: &Epub, work_id: u64) -> String {
+        format!("https://archiveofourown.org/works/{}", work_id)
+    }
+
+    fn parse_tags(
+        &self,
+        _epub: &Epub,
+        spine: &Spine,
+        search: SpineSearch,
+    ) -> AnyResult<ParsedAO3Tags> {
+        let (mut tags, document) = find_in_spine(spine, search, |doc| {
+            let Some(tags) = doc
+                .root()
+                .descendants()
+                .find(|it| it.has_tag_name("dl") && it.attribute("class").unwrap_or("") == "tags")
+            else {
+                return Ok(None);
+            };
+
+            let (tags_with_nodes, unknown_tags) = process_dt_dd_elements_to_hash_map(&tags)?;
+            if !unknown_tags.is_empty() {
+                warn!("Unknown tags encountered: {:?}", unknown_tags)
+            }
+            Ok(Some(ParsedAO3Tags::from_hash_map_of_ao3tags(
+                &tags_with_nodes,
+            )))
+        })?
+        .ok_or_else(|| FicError::new(FicErrorKind::MissingTags, "cannot parse document tags"))?;
+
+        tags.tags_document = Some(document);
+        add_notes(spine, &mut tags);
+        Ok(tags)
+    }
+}
+
+mkregex!(RE_AO3_WORK_URL, r"(?i)archiveofourown\.org/works/(\d+)");
+
+/// Collects the summary and notes of the work (preface and afterword) and of its chapters.
+/// Unreadable pages are skipped, as the notes are not essential.
+fn add_notes(spine: &Spine, tags: &mut ParsedAO3Tags) {
+    for doc in spine.parsed_documents() {
+        let mut chapter = ChapterNotes {
+            heading: doc
+                .descendants()
+                .find(|elt| elt.has_tag_name("h2") && elt.attribute("class") == Some("heading"))
+                .map(|heading| node_inner_text(&heading))
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        for (label, text) in labelled_blocks(doc) {
+            match label.as_str() {
+                "summary" => tags.summary = Some(text),
+                "notes" => tags.start_notes = Some(text),
+                "end notes" => tags.end_notes = Some(text),
+                "chapter summary" => chapter.summary = Some(text),
+                "chapter notes" => chapter.start_notes = Some(text),
+                "chapter end notes" => chapter.end_notes = Some(text),
+                _ => (),
+            }
+        }
+        if chapter.summary.is_some() || chapter.start_notes.is_some() || chapter.end_notes.is_some()
+        {
+            tags.chapter_notes.push(chapter);
+        }
+    }
+}
+
+type TagNodes<'a> = HashMap<AO3Tag<&'a str>, Node<'a, 'a>>;
+
+/// here `node` is expected to be a `<dl class="tags">` element
+fn process_dt_dd_elements_to_hash_map<'a>(
+    node: &'a Node<'a, 'a>,
+) -> AnyResult<(TagNodes<'a>, HashSet<&'a str>)> {
+    assert_eq!(node.attribute("class"), Some("tags"));
+    let mut result = TagNodes::<'a>::new();
+    let mut unknown_tags = HashSet::<&'a str>::new();
+
+    for (dt, dd) in node
+        .children()
+        // we're only interested in `<dt ...>` and `<dd ...>`
+        .filter(|elt| elt.is_element() && ["dt", "dd"].contains(&elt.tag_name().name()))
+        .tuples::<(_, _)>()
+    {
+        if dt.tag_name().name() != "dt" || dd.tag_name().name() != "dd" {
+            bail!(FicError::new(
+                FicErrorKind::TagLayout,
+                format!(
+                    "Tag pair mismatch during parsing: expected `(<dt ...>, <dd ...>)`, got `({}, {})`",
+                    full_node_text(&dt),
+                    full_node_text(&dd)
+                )
+            ))
+        }
+        // println!("{}", dt.text().unwrap());
+        let tag_text = dt.text().ok_or_else(|| {
+            FicError::new(
+                FicErrorKind::TagLayout,
+                format!("cannot find text of element `{:?}`", dt),
+            )
+        })?;
+        match result.entry(AO3Tag::match_str(tag_text)) {
+            Entry::Occupied(entry) => {
+                match entry.key() {
+                    AO3Tag::UnknownTag(unknown_tag) => unknown_tags.insert(unknown_tag),
+                    _ => bail!(FicError::new(
+                        FicErrorKind::DuplicateTag,
+                        format!(
+                            "several values found for tag `{}`, namely `{}` and `{}`",
+                            tag_text,
+                            full_node_text(entry.get()),
+                            full_node_text(&dd),
+                        )
+                    )),
+                };
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(dd);
+            }
+        }
+    }
+
+    Ok((result, unknown_tags))
+}
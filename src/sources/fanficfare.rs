@@ -0,0 +1,347 @@
+use std::sync::LazyLock;
+
+use anyhow::{bail, Result as AnyResult};
+use chrono::NaiveDate;
+use itertools::Itertools;
+use rbook::Epub;
+use regex::Regex;
+
+use super::{find_in_spine, labelled_values, SourceParser, Spine, SpineSearch};
+use crate::{
+    errors::{FicError, FicErrorKind},
+    tags::{AO3Stats, CompletionStatus, ParsedAO3Tags, SeriesMembership},
+    utils::{mkregex, static_with_lock},
+};
+
+/// Epubs made by FanFicFare, mostly from FanFiction.net. Tags come from the generated title
+/// page (`<b>Category:</b> ...<br/>`) and series from the `calibre:` OPF metadata.
+pub struct FanFicFareParser;
+
+/// Work URLs of a site FanFicFare downloads from, found in the OPF identifiers and sources.
+struct SiteUrl {
+    /// Matches the work URL, capturing the work identifier as `id` and, for sites with
+    /// several hosts, the host as `site`
+    pattern: Regex,
+    /// Canonical work URL, with `{site}` and `{id}` replaced by the captures
+    url: &'static str,
+    /// Whether another parser handles the epubs of this site when FanFicFare is not
+    /// credited, so that its URL alone does not mean FanFicFare
+    has_own_parser: bool,
+}
+
+impl SiteUrl {
+    fn new(pattern: &str, url: &'static str, has_own_parser: bool) -> Self {
+        SiteUrl {
+            pattern: Regex::new(pattern).unwrap(),
+            url,
+            has_own_parser,
+        }
+    }
+}
+
+static_with_lock!(
+    SITE_URLS,
+    Vec<SiteUrl>,
+    vec![
+        SiteUrl::new(
+            r"(?i)fanfiction\.net/s/(?<id>\d+)",
+            "https://www.fanfiction.net/s/{id}/1/",
+            false,
+        ),
+        SiteUrl::new(
+            r"(?i)fictionpress\.com/s/(?<id>\d+)",
+            "https://www.fictionpress.com/s/{id}/1/",
+            false,
+        ),
+        SiteUrl::new(
+            r"(?i)archiveofourown\.org/works/(?<id>\d+)",
+            "https://archiveofourown.org/works/{id}",
+            true,
+        ),
+        SiteUrl::new(
+            r"(?i)royalroad\.com/fiction/(?<id>\d+)",
+            "https://www.royalroad.com/fiction/{id}",
+            false,
+        ),
+        SiteUrl::new(
+            r"(?i)wattpad\.com/story/(?<id>\d+)",
+            "https://www.wattpad.com/story/{id}",
+            false,
+        ),
+        SiteUrl::new(
+            r"(?i)(?<site>forums?\.(?:spacebattles\.com|sufficientvelocity\.com|questionablequesting\.com))/threads/(?:[^/\s]*\.)?(?<id>\d+)",
+            "https://{site}/threads/{id}/",
+            false,
+        ),
+        // eFiction archives, like many smaller fandom archives
+        SiteUrl::new(
+            r"(?i)(?<site>[\w-]+(?:\.[\w-]+)+(?:/[\w.-]+)*)/viewstory\.php\?(?:[^\s#]*&)?sid=(?<id>\d+)",
+            "https://{site}/viewstory.php?sid={id}",
+            false,
+        ),
+    ]
+);
+
+/// Identifier and canonical URL of the work if `url` is the work URL of a known site,
+/// along with whether the site has a parser of its own.
+fn site_work(url: &str) -> Option<(u64, String, bool)> {
+    SITE_URLS.iter().find_map(|site| {
+        let caps = site.pattern.captures(url)?;
+        let id = caps["id"].parse().ok()?;
+        let site_name = caps
+            .name("site")
+            .map_or(String::new(), |site| site.as_str().to_lowercase());
+        let url = site
+            .url
+            .replace("{site}", &site_name)
+            .replace("{id}", &caps["id"]);
+        Some((id, url, site.has_own_parser))
+    })
+}
+
+/// The work of the first OPF identifier or source that is the URL of a known site.
+fn find_work(epub: &Epub) -> Option<(u64, String, bool)> {
+    let metadata = epub.metadata();
+    metadata
+        .get("identifier")
+        .into_iter()
+        .chain(metadata.get("source"))
+        .find_map(|elt| site_work(elt.value()))
+}
+
+impl SourceParser for FanFicFareParser {
+    fn name(&self) -> &'static str {
+        "fanficfare"
+    }
+
+    fn detect(&self, epub: &Epub, _spine: &Spine) -> bool {
+        epub.metadata()
+            .contributors()
+            .iter()
+            .any(|elt| elt.value().to_lowercase().contains("fanficfare"))
+            || find_work(epub).is_some_and(|(_, _, has_own_parser)| !has_own_parser)
+    }
+
+    /// Identifier of the work on the site of its source URL.
+    fn work_id(&self, epub: &Epub, _spine: &Spine) -> Option<u64> {
+        find_work(epub).map(|(work_id, _, _)| work_id)
+    }
+
+    fn work_url(&self, epub: &Epub, work_id: u64) -> String {
+        find_work(epub)
+            .filter(|(id, _, _)| *id == work_id)
+            .map_or_else(
+                || format!("https://www.fanfiction.net/s/{}/1/", work_id),
+                |(_, url, _)| url,
+            )
+    }
+
+    fn parse_tags(
+        &self,
+        epub: &Epub,
+        spine: &Spine,
+        search: SpineSearch,
+    ) -> AnyResult<ParsedAO3Tags> {
+        let is_detected = self.detect(epub, spine);
+        let mut tags = match find_title_page_values(epub, spine, search, is_detected)? {
+            Some((values, document)) => ParsedAO3Tags {
+                tags_document: Some(document),
+                ..tags_from_title_page(&values)
+            },
+            None => tags_from_opf_subjects(epub),
+        };
+        tags.series = series_from_calibre_metadata(epub);
+        Ok(tags)
+    }
+}
+
+mkregex!(RE_PAIRING_BRACKETS, r"\[([^\]]+)\]");
+
+type LabelledValues = Vec<(String, String)>;
+
+/// Labelled values of the title page, with the path of its document. Without a title page,
+/// `None` tells to fall back on the OPF subjects, which only makes sense when the epub is
+/// known to come from FanFicFare.
+fn find_title_page_values(
+    epub: &Epub,
+    spine: &Spine,
+    search: SpineSearch,
+    is_detected: bool,
+) -> AnyResult<Option<(LabelledValues, String)>> {
+    let found = find_in_spine(spine, search, |doc| {
+        let values = labelled_values(doc);
+        Ok(values
+            .iter()
+            .any(|(label, _)| ["category", "status", "rating"].contains(&&*label.to_lowercase()))
+            .then_some(values))
+    });
+    if let Ok(Some(found)) = found {
+        return Ok(Some(found));
+    }
+    if !is_detected || epub.metadata().subject().is_empty() {
+        bail!(FicError::new(
+            FicErrorKind::MissingTags,
+            "cannot find FanFicFare title page"
+        ));
+    }
+    Ok(None)
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// FanFiction.net lists pairings as `[A, B] [C, D]`, FanFicFare sometimes as `A/B, C/D`.
+fn split_pairings(value: &str) -> Vec<String> {
+    let bracketed = RE_PAIRING_BRACKETS
+        .captures_iter(value)
+        .map(|caps| split_list(&caps[1]).join("/"))
+        .collect::<Vec<_>>();
+    if bracketed.is_empty() {
+        split_list(value)
+    } else {
+        bracketed
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    ["%Y-%m-%d", "%m/%d/%Y", "%d %b %Y", "%B %d, %Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value.trim(), format).ok())
+}
+
+fn parse_number<N: std::str::FromStr>(value: &str) -> Option<N> {
+    value.replace([',', ' '], "").parse().ok()
+}
+
+fn tags_from_title_page(values: &[(String, String)]) -> ParsedAO3Tags {
+    let mut tags = ParsedAO3Tags::default();
+    let mut stats = AO3Stats::default();
+    for (label, value) in values {
+        match label.to_lowercase().as_str() {
+            "category" | "fandom" | "fandoms" => tags.fandoms = split_list(value),
+            "genre" | "genres" => {
+                tags.additional_tags = value
+                    .split(['/', ','])
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            "characters" => tags.characters = split_list(value),
+            "relationships" | "pairings" | "ships" => tags.relationships = split_pairings(value),
+            "rating" => tags.rating = Some(value.clone()),
+            "language" => tags.language = Some(value.clone()),
+            "warnings" => tags.archive_warnings = split_list(value),
+            "status" => {
+                stats.status = Some(if value.to_lowercase().starts_with("complete") {
+                    CompletionStatus::Complete
+                } else {
+                    CompletionStatus::InProgress
+                })
+            }
+            "published" => stats.published = parse_date(value),
+            "updated" => stats.updated = parse_date(value),
+            "completed" => stats.completed = parse_date(value),
+            "words" => stats.words = parse_number(value),
+            "chapters" => stats.chapters = parse_number(value),
+            _ => (),
+        }
+    }
+    if stats.status == Some(CompletionStatus::Complete) {
+        stats.planned_chapters = stats.chapters;
+    }
+    tags.stats = Some(
+        values
+            .iter()
+            .filter(|(label, _)| {
+                ["status", "published", "updated", "words", "chapters"]
+                    .contains(&&*label.to_lowercase())
+            })
+            .map(|(label, value)| format!("{}: {}", label, value))
+            .join(" "),
+    )
+    .filter(|stats| !stats.is_empty());
+    tags.parsed_stats = Some(stats);
+    tags
+}
+
+/// Without a title page, the OPF subjects (category, genres and status) are all we have.
+fn tags_from_opf_subjects(epub: &Epub) -> ParsedAO3Tags {
+    ParsedAO3Tags {
+        additional_tags: epub
+            .metadata()
+            .subject()
+            .iter()
+            .map(|elt| elt.value().to_string())
+            .collect(),
+        language: epub.metadata().language().map(|elt| elt.value().into()),
+        ..Default::default()
+    }
+}
+
+fn series_from_calibre_metadata(epub: &Epub) -> Vec<SeriesMembership> {
+    let metadata = epub.metadata();
+    let Some(name) = metadata.get("series").first().map(|elt| elt.value().trim()) else {
+        return vec![];
+    };
+    vec![SeriesMembership {
+        name: name.into(),
+        part: metadata
+            .get("series_index")
+            .first()
+            .and_then(|elt| elt.value().parse::<f64>().ok())
+            .map(|part| part as u32),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_work_of_each_site() {
+        let work = |url| site_work(url).map(|(id, url, _)| (id, url));
+        assert_eq!(
+            work("https://www.fanfiction.net/s/1234567/3/Some-Title"),
+            Some((1234567, "https://www.fanfiction.net/s/1234567/1/".into()))
+        );
+        assert_eq!(
+            work("http://archiveofourown.org/works/42/chapters/7"),
+            Some((42, "https://archiveofourown.org/works/42".into()))
+        );
+        assert_eq!(
+            work("https://forums.SpaceBattles.com/threads/some-quest.98765/"),
+            Some((
+                98765,
+                "https://forums.spacebattles.com/threads/98765/".into()
+            ))
+        );
+        assert_eq!(
+            work("https://www.hpfanficarchive.com/stories/viewstory.php?chapter=2&sid=555"),
+            Some((
+                555,
+                "https://www.hpfanficarchive.com/stories/viewstory.php?sid=555".into()
+            ))
+        );
+        assert_eq!(work("urn:uuid:1234-5678"), None);
+    }
+
+    #[test]
+    fn only_sites_without_own_parser_mean_fanficfare() {
+        let has_own_parser = |url| site_work(url).map(|(_, _, has_own_parser)| has_own_parser);
+        assert_eq!(
+            has_own_parser("https://archiveofourown.org/works/1"),
+            Some(true)
+        );
+        assert_eq!(
+            has_own_parser("https://www.royalroad.com/fiction/1"),
+            Some(false)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::LazyLock;
+
+use anyhow::Result as AnyResult;
+use itertools::Itertools;
+use rbook::Epub;
+use regex::Regex;
+
+use super::{find_in_spine, labelled_values, SourceParser, Spine, SpineSearch};
+use crate::{
+    errors::{FicError, FicErrorKind},
+    tags::{AO3Stats, AO3Tag, CompletionStatus, ParsedAO3Tags},
+    utils::mkregex,
+};
+
+/// Epubs downloaded from Ficbook.net. The first page holds the work header as
+/// `<strong>Рейтинг:</strong> R` lines, with labels in Russian.
+pub struct FicbookParser;
+
+impl SourceParser for FicbookParser {
+    fn name(&self) -> &'static str {
+        "ficbook"
+    }
+
+    fn detect(&self, epub: &Epub, _spine: &Spine) -> bool {
+        let metadata = epub.metadata();
+        metadata
+            .publisher()
+            .into_iter()
+            .chain(metadata.get("identifier"))
+            .chain(metadata.get("source"))
+            .any(|elt| elt.value().to_lowercase().contains("ficbook"))
+    }
+
+    /// Identifier of the work in the `ficbook.net/readfic/<id>` URL. Newer works have
+    /// non-numeric identifiers, which are not reported.
+    fn work_id(&self, epub: &Epub, spine: &Spine) -> Option<u64> {
+        let find_in = |s: &str| {
+            RE_FICBOOK_WORK_URL
+                .captures(s)
+                .and_then(|caps| caps[1].parse().ok())
+        };
+        let metadata = epub.metadata();
+        metadata
+            .get("identifier")
+            .into_iter()
+            .chain(metadata.get("source"))
+            .find_map(|elt| find_in(elt.value()))
+            .or_else(|| find_in(spine.text(0)?))
+    }
+
+    fn work_url(&self, _epub: &Epub, work_id: u64) -> String {
+        format!("https://ficbook.net/readfic/{}", work_id)
+    }
+
+    fn parse_tags(
+        &self,
+        epub: &Epub,
+        spine: &Spine,
+        search: SpineSearch,
+    ) -> AnyResult<ParsedAO3Tags> {
+        let (values, document) = find_in_spine(spine, search, |doc| {
+            let values = labelled_values(doc)
+                .into_iter()
+                .map(|(label, value)| (AO3Tag::match_str(label), value))
+                .collect::<Vec<_>>();
+            Ok(values
+                .iter()
+                .any(|(tag, _)| matches!(tag, AO3Tag::Rating | AO3Tag::Fandoms))
+                .then_some(values))
+        })?
+        .ok_or_else(|| {
+            FicError::new(FicErrorKind::MissingTags, "cannot find ficbook work header")
+        })?;
+        let mut tags = tags_from_header(&values);
+        tags.tags_document = Some(document);
+        if tags.language.is_none() {
+            tags.language = epub.metadata().language().map(|elt| elt.value().into());
+        }
+        Ok(tags)
+    }
+}
+
+mkregex!(RE_FICBOOK_WORK_URL, r"(?i)ficbook\.net/readfic/(\d+)\b");
+mkregex!(RE_SIZE_PARTS, r"(?i)(\d+)\s*част");
+// Thousands are separated by (non-breaking) spaces: `41 523 слова`.
+mkregex!(RE_SIZE_WORDS, r"(?i)(\d[\d\s]*)слов");
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn tags_from_header(values: &[(AO3Tag<String>, String)]) -> ParsedAO3Tags {
+    let mut tags = ParsedAO3Tags::default();
+    let mut stats = AO3Stats::default();
+    for (tag, value) in values {
+        match tag {
+            AO3Tag::Rating => tags.rating = Some(value.clone()),
+            AO3Tag::ArchiveWarnings => tags.archive_warnings = split_list(value),
+            AO3Tag::Categories => tags.categories = split_list(value),
+            AO3Tag::Fandoms => tags.fandoms = split_list(value),
+            AO3Tag::Relationships => tags.relationships = split_list(value),
+            AO3Tag::Characters => tags.characters = split_list(value),
+            // Pairings are written `A/B` among the plain character names.
+            AO3Tag::RelationshipsAndCharacters => {
+                let (relationships, characters): (Vec<_>, Vec<_>) = split_list(value)
+                    .into_iter()
+                    .partition(|item| item.contains('/'));
+                tags.relationships.extend(relationships);
+                tags.characters.extend(characters);
+            }
+            AO3Tag::AdditionalTags => tags.additional_tags = split_list(value),
+            AO3Tag::Language => tags.language = Some(value.clone()),
+            AO3Tag::Status => {
+                let status = value.to_lowercase();
+                stats.status = if status.starts_with("заверш") || status.starts_with("complete")
+                {
+                    Some(CompletionStatus::Complete)
+                } else {
+                    Some(CompletionStatus::InProgress)
+                }
+            }
+            AO3Tag::Stats => {
+                stats.chapters = RE_SIZE_PARTS
+                    .captures(value)
+                    .and_then(|caps| caps[1].parse().ok());
+                stats.words = RE_SIZE_WORDS.captures(value).and_then(|caps| {
+                    caps[1]
+                        .chars()
+                        .filter(|c| !c.is_whitespace())
+                        .collect::<String>()
+                        .parse()
+                        .ok()
+                })
+            }
+            AO3Tag::Series | AO3Tag::UnknownTag(_) => (),
+        }
+    }
+    if stats.status == Some(CompletionStatus::Complete) {
+        stats.planned_chapters = stats.chapters;
+    }
+    tags.stats = Some(
+        values
+            .iter()
+            .filter(|(tag, _)| matches!(tag, AO3Tag::Status | AO3Tag::Stats))
+            .map(|(_, value)| value)
+            .join(", "),
+    )
+    .filter(|stats| !stats.is_empty());
+    tags.parsed_stats = Some(stats);
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_size() {
+        let tags = tags_from_header(&[
+            (AO3Tag::Status, "Завершён".into()),
+            (
+                AO3Tag::Stats,
+                "150 страниц, 41\u{a0}523 слова, 12 частей".into(),
+            ),
+        ]);
+        let stats = tags.parsed_stats.unwrap();
+        assert_eq!(stats.words, Some(41523));
+        assert_eq!(stats.chapters, Some(12));
+        assert_eq!(stats.planned_chapters, Some(12));
+        assert_eq!(stats.status, Some(CompletionStatus::Complete));
+    }
+
+    #[test]
+    fn size_without_words() {
+        let tags = tags_from_header(&[(AO3Tag::Stats, "Мини, 1 часть".into())]);
+        let stats = tags.parsed_stats.unwrap();
+        assert_eq!(stats.words, None);
+        assert_eq!(stats.chapters, Some(1));
+    }
+}
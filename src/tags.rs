@@ -1,28 +1,111 @@
-use std::{borrow::Borrow, collections::HashMap, sync::LazyLock};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::LazyLock,
+};
 
+use chrono::NaiveDate;
+use log::trace;
 use regex::Regex;
 use roxmltree::Node;
 
-use crate::utils::{mkregex, parse_sequence_of_node_text_children, vec_as_newlines};
+use crate::utils::{
+    mkregex, node_inner_text, node_links, parse_sequence_of_node_text_children, static_with_lock,
+};
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ParsedAO3Tags {
     pub rating: Option<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
     pub archive_warnings: Vec<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
     pub categories: Vec<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
     pub fandoms: Vec<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
     pub relationships: Vec<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
+    /// `relationships` split into their members, in the same order; `None` for the tags
+    /// that do not name several characters
+    #[serde(default)]
+    pub parsed_relationships: Vec<Option<Relationship>>,
     pub characters: Vec<String>,
-    #[serde(serialize_with = "vec_as_newlines")]
     pub additional_tags: Vec<String>,
     pub language: Option<String>,
-    pub series: Option<String>,
+    #[serde(default)]
+    pub series: Vec<SeriesMembership>,
     pub stats: Option<String>,
+    pub parsed_stats: Option<AO3Stats>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Author's notes at the beginning of the work
+    #[serde(default)]
+    pub start_notes: Option<String>,
+    /// Author's notes at the end of the work
+    #[serde(default)]
+    pub end_notes: Option<String>,
+    /// Summary and notes of the chapters that have any
+    #[serde(default)]
+    pub chapter_notes: Vec<ChapterNotes>,
+    /// Path of the spine document the tags were found in
+    #[serde(default)]
+    pub tags_document: Option<String>,
+    /// Archive page of each tag, by kind and tag name
+    #[serde(default)]
+    pub tag_links: BTreeMap<TagKind, BTreeMap<String, String>>,
+}
+
+/// Kind of a work tag.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TagKind {
+    Rating,
+    ArchiveWarning,
+    Category,
+    Fandom,
+    Relationship,
+    Character,
+    AdditionalTag,
+}
+
+impl TagKind {
+    pub const ALL: [Self; 7] = [
+        Self::Rating,
+        Self::ArchiveWarning,
+        Self::Category,
+        Self::Fandom,
+        Self::Relationship,
+        Self::Character,
+        Self::AdditionalTag,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rating => "rating",
+            Self::ArchiveWarning => "archive_warning",
+            Self::Category => "category",
+            Self::Fandom => "fandom",
+            Self::Relationship => "relationship",
+            Self::Character => "character",
+            Self::AdditionalTag => "additional_tag",
+        }
+    }
+
+    fn of_ao3_tag<S: Borrow<str>>(tag: &AO3Tag<S>) -> Option<Self> {
+        match tag {
+            AO3Tag::Rating => Some(Self::Rating),
+            AO3Tag::ArchiveWarnings => Some(Self::ArchiveWarning),
+            AO3Tag::Categories => Some(Self::Category),
+            AO3Tag::Fandoms => Some(Self::Fandom),
+            AO3Tag::Relationships => Some(Self::Relationship),
+            AO3Tag::Characters => Some(Self::Character),
+            AO3Tag::AdditionalTags => Some(Self::AdditionalTag),
+            AO3Tag::RelationshipsAndCharacters
+            | AO3Tag::Language
+            | AO3Tag::Series
+            | AO3Tag::Status
+            | AO3Tag::Stats
+            | AO3Tag::UnknownTag(_) => None,
+        }
+    }
 }
 
 impl ParsedAO3Tags {
@@ -36,13 +119,293 @@ impl ParsedAO3Tags {
             characters: get_tag_vec(&AO3Tag::Characters, hash_map),
             additional_tags: get_tag_vec(&AO3Tag::AdditionalTags, hash_map),
             language: get_tag_opt(&AO3Tag::Language, hash_map),
-            series: get_tag_opt(&AO3Tag::Series, hash_map),
-            stats: get_tag_opt(&AO3Tag::Stats, hash_map),
+            series: hash_map
+                .get(&AO3Tag::Series)
+                .map(SeriesMembership::parse_series_tag)
+                .unwrap_or_default(),
+            stats: get_tag_text(&AO3Tag::Stats, hash_map),
+            parsed_stats: get_tag_text(&AO3Tag::Stats, hash_map).map(|s| AO3Stats::parse(&s)),
+            tag_links: hash_map
+                .iter()
+                .filter_map(|(tag, node)| {
+                    let links = node_links(node)
+                        .map(|(name, url)| (name, url.to_string()))
+                        .collect::<BTreeMap<_, _>>();
+                    (!links.is_empty()).then_some((TagKind::of_ao3_tag(tag)?, links))
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Tags of the given kind.
+    pub fn tags_of_kind(&self, kind: TagKind) -> &[String] {
+        match kind {
+            TagKind::Rating => self.rating.as_slice(),
+            TagKind::ArchiveWarning => &self.archive_warnings,
+            TagKind::Category => &self.categories,
+            TagKind::Fandom => &self.fandoms,
+            TagKind::Relationship => &self.relationships,
+            TagKind::Character => &self.characters,
+            TagKind::AdditionalTag => &self.additional_tags,
+        }
+    }
+
+    /// Multi-valued tag fields together with their kind.
+    pub fn tag_lists(&self) -> [(TagKind, &[String]); 6] {
+        [
+            TagKind::ArchiveWarning,
+            TagKind::Category,
+            TagKind::Fandom,
+            TagKind::Relationship,
+            TagKind::Character,
+            TagKind::AdditionalTag,
+        ]
+        .map(|kind| (kind, self.tags_of_kind(kind)))
+    }
+
+    /// Every tag field, the rating included, together with its kind.
+    pub fn all_tags(&self) -> [(TagKind, &[String]); 7] {
+        TagKind::ALL.map(|kind| (kind, self.tags_of_kind(kind)))
+    }
+
+    /// Archive page of the tag `name` of the given kind, if the epub recorded it.
+    pub fn tag_url(&self, kind: TagKind, name: &str) -> Option<&str> {
+        self.tag_links.get(&kind)?.get(name).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelationshipKind {
+    /// `A/B`
+    Romantic,
+    /// `A & B`
+    Platonic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RelationshipMember {
+    pub name: String,
+    /// Fandom or other precision in parentheses after the name, as in `Peter Parker (Marvel)`
+    pub disambiguator: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Relationship {
+    pub members: Vec<RelationshipMember>,
+    pub kind: RelationshipKind,
+}
+
+impl Relationship {
+    /// Parses a relationship tag such as `Draco Malfoy/Harry Potter` or
+    /// `Natasha Romanov (Marvel) & Clint Barton`. Separators inside parentheses are part of
+    /// the disambiguator; a tag mixing both separators counts as romantic. Tags naming
+    /// fewer than two characters, such as `Original Character`, are not relationships.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let mut members = vec![];
+        let mut kind = RelationshipKind::Platonic;
+        let mut member_start = 0;
+        let mut depth = 0usize;
+        for (i, c) in tag.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                '/' | '&' if depth == 0 => {
+                    if c == '/' {
+                        kind = RelationshipKind::Romantic;
+                    }
+                    members.push(&tag[member_start..i]);
+                    member_start = i + 1;
+                }
+                _ => (),
+            }
+        }
+        members.push(&tag[member_start..]);
+
+        let members = members
+            .into_iter()
+            .map(str::trim)
+            .filter(|member| !member.is_empty())
+            .map(RelationshipMember::parse)
+            .collect::<Vec<_>>();
+        (members.len() >= 2).then_some(Relationship { members, kind })
+    }
+}
+
+impl RelationshipMember {
+    fn parse(member: &str) -> Self {
+        match RE_DISAMBIGUATED_NAME.captures(member) {
+            Some(caps) => RelationshipMember {
+                name: caps[1].trim().into(),
+                disambiguator: Some(caps[2].trim().into()),
+            },
+            None => RelationshipMember {
+                name: member.into(),
+                disambiguator: None,
+            },
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+mkregex!(RE_DISAMBIGUATED_NAME, r"^(.*?)\s*\(([^()]*)\)$");
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeriesMembership {
+    pub name: String,
+    pub part: Option<u32>,
+}
+
+impl SeriesMembership {
+    /// Parses the contents of the series tag, `Part 3 of <a>Series</a>, Part 1 of <a>Other</a>`.
+    pub fn parse_series_tag(node: &Node) -> Vec<Self> {
+        let links = node
+            .children()
+            .filter(|elt| elt.has_tag_name("a"))
+            .collect::<Vec<_>>();
+        if links.is_empty() {
+            return Self::parse_series_text(&node_inner_text(node));
+        }
+
+        let mut preceding_text = String::new();
+        let mut result = vec![];
+        for child in node.children() {
+            if child.has_tag_name("a") {
+                result.push(SeriesMembership {
+                    name: node_inner_text(&child),
+                    part: RE_SERIES_PART
+                        .captures_iter(&preceding_text)
+                        .last()
+                        .and_then(|caps| caps[1].parse().ok()),
+                });
+                preceding_text.clear();
+            } else {
+                preceding_text.push(' ');
+                preceding_text.push_str(&node_inner_text(&child));
+            }
+        }
+        result
+    }
+
+    /// Parses series as plain text, `Part 3 of Series, Part 1 of Other`, like older versions
+    /// stored them.
+    fn parse_series_text(text: &str) -> Vec<Self> {
+        let parts = RE_SERIES_PART.captures_iter(text).collect::<Vec<_>>();
+        if parts.is_empty() {
+            return match text.trim() {
+                "" => vec![],
+                name => vec![SeriesMembership {
+                    name: name.into(),
+                    part: None,
+                }],
+            };
+        }
+        parts
+            .iter()
+            .enumerate()
+            .map(|(i, caps)| {
+                let name_start = caps.get(0).unwrap().end();
+                let name_end = parts
+                    .get(i + 1)
+                    .map_or(text.len(), |next| next.get(0).unwrap().start());
+                SeriesMembership {
+                    name: text[name_start..name_end]
+                        .trim()
+                        .trim_end_matches(',')
+                        .trim()
+                        .into(),
+                    part: caps[1].parse().ok(),
+                }
+            })
+            .filter(|series| !series.name.is_empty())
+            .collect()
+    }
+}
+
+mkregex!(RE_SERIES_PART, r"(?i)part\s*(\d+)\s*of");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CompletionStatus {
+    Complete,
+    InProgress,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChapterNotes {
+    /// Chapter heading, e.g. `Chapter 3: Title`
+    pub heading: String,
+    pub summary: Option<String>,
+    pub start_notes: Option<String>,
+    pub end_notes: Option<String>,
+}
+
+/// The AO3 stats line (`Published: ... Words: ... Chapters: 5/? ...`) split into fields.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AO3Stats {
+    pub published: Option<NaiveDate>,
+    pub updated: Option<NaiveDate>,
+    pub completed: Option<NaiveDate>,
+    pub words: Option<u64>,
+    pub chapters: Option<u32>,
+    /// `None` when the total number of chapters is unknown (`5/?`)
+    pub planned_chapters: Option<u32>,
+    pub status: Option<CompletionStatus>,
+    pub kudos: Option<u64>,
+    pub hits: Option<u64>,
+    pub bookmarks: Option<u64>,
+    pub comments: Option<u64>,
+}
+
+impl AO3Stats {
+    pub fn parse(stats: &str) -> Self {
+        fn date(re: &Regex, stats: &str) -> Option<NaiveDate> {
+            re.captures(stats)
+                .and_then(|caps| NaiveDate::parse_from_str(&caps[1], "%Y-%m-%d").ok())
+        }
+        fn number<N: FromStr>(re: &Regex, stats: &str) -> Option<N> {
+            re.captures(stats)
+                .and_then(|caps| caps[1].replace([',', ' ', '\u{a0}'], "").parse().ok())
+        }
+
+        let mut result = AO3Stats {
+            published: date(&RE_STATS_PUBLISHED, stats),
+            updated: date(&RE_STATS_UPDATED, stats),
+            completed: date(&RE_STATS_COMPLETED, stats),
+            words: number(&RE_STATS_WORDS, stats),
+            kudos: number(&RE_STATS_KUDOS, stats),
+            hits: number(&RE_STATS_HITS, stats),
+            bookmarks: number(&RE_STATS_BOOKMARKS, stats),
+            comments: number(&RE_STATS_COMMENTS, stats),
+            ..Default::default()
+        };
+        if let Some(caps) = RE_STATS_CHAPTERS.captures(stats) {
+            result.chapters = caps[1].parse().ok();
+            result.planned_chapters = caps[2].parse().ok();
+            result.status = Some(match (result.chapters, result.planned_chapters) {
+                (Some(chapters), Some(planned)) if chapters >= planned => {
+                    CompletionStatus::Complete
+                }
+                _ => CompletionStatus::InProgress,
+            });
+        }
+        if result.completed.is_some() {
+            result.status = Some(CompletionStatus::Complete);
+        }
+        result
+    }
+}
+
+mkregex!(RE_STATS_PUBLISHED, r"(?i)published:\s*(\d{4}-\d{2}-\d{2})");
+mkregex!(RE_STATS_UPDATED, r"(?i)updated:\s*(\d{4}-\d{2}-\d{2})");
+mkregex!(RE_STATS_COMPLETED, r"(?i)completed:\s*(\d{4}-\d{2}-\d{2})");
+mkregex!(RE_STATS_WORDS, r"(?i)words:\s*([\d,\s]*\d)");
+mkregex!(RE_STATS_CHAPTERS, r"(?i)chapters:\s*(\d+)\s*/\s*(\d+|\?)");
+mkregex!(RE_STATS_KUDOS, r"(?i)kudos:\s*([\d,]+)");
+mkregex!(RE_STATS_HITS, r"(?i)hits:\s*([\d,]+)");
+mkregex!(RE_STATS_BOOKMARKS, r"(?i)bookmarks:\s*([\d,]+)");
+mkregex!(RE_STATS_COMMENTS, r"(?i)comments:\s*([\d,]+)");
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AO3Tag<S: Borrow<str>> {
     Rating,
     ArchiveWarnings,
@@ -50,53 +413,105 @@ pub enum AO3Tag<S: Borrow<str>> {
     Fandoms,
     Relationships,
     Characters,
+    /// A single list mixing pairings and characters, as on ficbook.net
+    RelationshipsAndCharacters,
     AdditionalTags,
     Language,
     Series,
+    /// Completion status, when the archive gives it separately from the stats
+    Status,
     Stats,
     UnknownTag(S),
 }
 
 impl<S: Borrow<str>> AO3Tag<S> {
+    /// Recognises a tag label in any of the languages of [`TAG_LABELS`].
     pub fn match_str(s: S) -> Self {
-        if RE_RATING.find(s.borrow()).is_some() {
-            Self::Rating
-        } else if RE_ARCHIVE_WARNINGS.find(s.borrow()).is_some() {
-            Self::ArchiveWarnings
-        } else if RE_CATEGORIES.find(s.borrow()).is_some() {
-            Self::Categories
-        } else if RE_FANDOMS.find(s.borrow()).is_some() {
-            Self::Fandoms
-        } else if RE_RELATIONSHIPS.find(s.borrow()).is_some() {
-            Self::Relationships
-        } else if RE_CHARACTERS.find(s.borrow()).is_some() {
-            Self::Characters
-        } else if RE_ADDITIONAL_TAGS.find(s.borrow()).is_some() {
-            Self::AdditionalTags
-        } else if RE_LANGUAGE.find(s.borrow()).is_some() {
-            Self::Language
-        } else if RE_SERIES.find(s.borrow()).is_some() {
-            Self::Series
-        } else if RE_STATS.find(s.borrow()).is_some() {
-            Self::Stats
-        } else {
-            Self::UnknownTag(s)
-        }
-    }
-
-    // pub fn parse_tag_contents<'a>(&self, dd_tag: &'a Node<'a, 'a>) ->
-}
-
-mkregex!(RE_RATING, r"(?i).*rating.*");
-mkregex!(RE_ARCHIVE_WARNINGS, r"(?i).*archiv.*warn.*");
-mkregex!(RE_CATEGORIES, r"(?i).*categor.*");
-mkregex!(RE_FANDOMS, r"(?i).*fandom.*");
-mkregex!(RE_RELATIONSHIPS, r"(?i).*relationship.*");
-mkregex!(RE_CHARACTERS, r"(?i).*character.*");
-mkregex!(RE_ADDITIONAL_TAGS, r"(?i).*addit.*tag.*");
-mkregex!(RE_LANGUAGE, r"(?i).*lang.*");
-mkregex!(RE_SERIES, r"(?i).*series.*");
-mkregex!(RE_STATS, r"(?i).*stat.*");
+        match TAG_LABELS
+            .iter()
+            .find(|label| label.pattern.is_match(s.borrow()))
+        {
+            Some(label) => {
+                trace!(
+                    "label `{}` recognised as {:?} ({})",
+                    s.borrow(),
+                    label.tag,
+                    label.language
+                );
+                label.tag.with_unknown_type()
+            }
+            None => Self::UnknownTag(s),
+        }
+    }
+
+    fn with_unknown_type<T: Borrow<str>>(&self) -> AO3Tag<T> {
+        match self {
+            Self::Rating => AO3Tag::Rating,
+            Self::ArchiveWarnings => AO3Tag::ArchiveWarnings,
+            Self::Categories => AO3Tag::Categories,
+            Self::Fandoms => AO3Tag::Fandoms,
+            Self::Relationships => AO3Tag::Relationships,
+            Self::Characters => AO3Tag::Characters,
+            Self::RelationshipsAndCharacters => AO3Tag::RelationshipsAndCharacters,
+            Self::AdditionalTags => AO3Tag::AdditionalTags,
+            Self::Language => AO3Tag::Language,
+            Self::Series => AO3Tag::Series,
+            Self::Status => AO3Tag::Status,
+            Self::Stats => AO3Tag::Stats,
+            Self::UnknownTag(_) => unreachable!("unknown tags have no label pattern"),
+        }
+    }
+}
+
+/// Pattern recognising the label of a tag kind in one language.
+struct TagLabel {
+    language: &'static str,
+    tag: AO3Tag<&'static str>,
+    pattern: Regex,
+}
+
+// Label patterns tried in order, the first match wins. More specific patterns of a language
+// must come before the more general ones (e.g. "pairing and characters" before "characters").
+static_with_lock!(
+    TAG_LABELS,
+    Vec<TagLabel>,
+    [
+        ("en", AO3Tag::Rating, r"(?i).*rating.*"),
+        ("en", AO3Tag::ArchiveWarnings, r"(?i).*archiv.*warn.*"),
+        ("en", AO3Tag::Categories, r"(?i).*categor.*"),
+        ("en", AO3Tag::Fandoms, r"(?i).*fandom.*"),
+        ("en", AO3Tag::Relationships, r"(?i).*relationship.*"),
+        ("en", AO3Tag::Characters, r"(?i).*character.*"),
+        ("en", AO3Tag::AdditionalTags, r"(?i).*addit.*tag.*"),
+        ("en", AO3Tag::Language, r"(?i).*lang.*"),
+        ("en", AO3Tag::Series, r"(?i).*series.*"),
+        ("en", AO3Tag::Status, r"(?i)^\W*status\W*$"),
+        ("en", AO3Tag::Stats, r"(?i).*stat.*"),
+        ("ru", AO3Tag::Rating, r"(?i)рейтинг"),
+        ("ru", AO3Tag::ArchiveWarnings, r"(?i)предупреждени"),
+        ("ru", AO3Tag::Categories, r"(?i)направленност"),
+        ("ru", AO3Tag::Fandoms, r"(?i)ф[эа]ндом|ориджинал"),
+        (
+            "ru",
+            AO3Tag::RelationshipsAndCharacters,
+            r"(?i)п[эе]йринг.*персонаж",
+        ),
+        ("ru", AO3Tag::Relationships, r"(?i)п[эе]йринг"),
+        ("ru", AO3Tag::Characters, r"(?i)персонаж"),
+        ("ru", AO3Tag::AdditionalTags, r"(?i)метки|теги"),
+        ("ru", AO3Tag::Language, r"(?i)язык"),
+        ("ru", AO3Tag::Series, r"(?i)сборник|серия"),
+        ("ru", AO3Tag::Status, r"(?i)статус"),
+        ("ru", AO3Tag::Stats, r"(?i)размер|статистик"),
+    ]
+    .into_iter()
+    .map(|(language, tag, pattern)| TagLabel {
+        language,
+        tag,
+        pattern: Regex::new(pattern).unwrap(),
+    })
+    .collect()
+);
 
 fn get_tag_opt<'a>(
     tag: &AO3Tag<&'a str>,
@@ -111,6 +526,17 @@ fn get_tag_opt<'a>(
     }
 }
 
+/// Whole text of the tag, including the text of nested elements.
+fn get_tag_text<'a>(
+    tag: &AO3Tag<&'a str>,
+    hash_map: &'a HashMap<AO3Tag<&'a str>, Node<'a, 'a>>,
+) -> Option<String> {
+    hash_map
+        .get(tag)
+        .map(node_inner_text)
+        .filter(|text| !text.is_empty())
+}
+
 fn get_tag_vec<'a>(
     tag: &AO3Tag<&'a str>,
     hash_map: &'a HashMap<AO3Tag<&'a str>, Node<'a, 'a>>,
@@ -123,3 +549,172 @@ fn get_tag_vec<'a>(
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()
+    }
+
+    #[test]
+    fn parses_stats_of_work_in_progress() {
+        let stats = AO3Stats::parse(
+            "Published: 2020-01-01 Updated: 2020-03-04 Words: 12,345 Chapters: 2/? \
+             Kudos: 1,100 Hits: 20,000 Bookmarks: 10 Comments: 5",
+        );
+        assert_eq!(stats.published, date("2020-01-01"));
+        assert_eq!(stats.updated, date("2020-03-04"));
+        assert_eq!(stats.completed, None);
+        assert_eq!(stats.words, Some(12345));
+        assert_eq!(stats.chapters, Some(2));
+        assert_eq!(stats.planned_chapters, None);
+        assert_eq!(stats.status, Some(CompletionStatus::InProgress));
+        assert_eq!(stats.kudos, Some(1100));
+        assert_eq!(stats.hits, Some(20000));
+        assert_eq!(stats.bookmarks, Some(10));
+        assert_eq!(stats.comments, Some(5));
+    }
+
+    #[test]
+    fn parses_stats_of_completed_work() {
+        let stats = AO3Stats::parse(
+            "Published: 2019-05-06 Completed: 2019-07-08 Words: 1 234 Chapters: 3/3",
+        );
+        assert_eq!(stats.completed, date("2019-07-08"));
+        assert_eq!(stats.words, Some(1234));
+        assert_eq!(stats.chapters, Some(3));
+        assert_eq!(stats.planned_chapters, Some(3));
+        assert_eq!(stats.status, Some(CompletionStatus::Complete));
+        assert_eq!(stats.kudos, None);
+    }
+
+    #[test]
+    fn completion_follows_chapter_counts() {
+        let all_posted = AO3Stats::parse("Chapters: 10/10");
+        assert_eq!(all_posted.status, Some(CompletionStatus::Complete));
+        let some_posted = AO3Stats::parse("Chapters: 3/10");
+        assert_eq!(some_posted.planned_chapters, Some(10));
+        assert_eq!(some_posted.status, Some(CompletionStatus::InProgress));
+    }
+
+    fn member(name: &str, disambiguator: Option<&str>) -> RelationshipMember {
+        RelationshipMember {
+            name: name.into(),
+            disambiguator: disambiguator.map(String::from),
+        }
+    }
+
+    #[test]
+    fn slash_makes_romantic_relationship() {
+        let relationship = Relationship::parse("Draco Malfoy/Harry Potter").unwrap();
+        assert_eq!(relationship.kind, RelationshipKind::Romantic);
+        assert_eq!(
+            relationship.members,
+            [member("Draco Malfoy", None), member("Harry Potter", None)]
+        );
+    }
+
+    #[test]
+    fn ampersand_makes_platonic_relationship() {
+        let relationship =
+            Relationship::parse("Natasha Romanov (Marvel) & Clint Barton & Wanda (Marvel/MCU)")
+                .unwrap();
+        assert_eq!(relationship.kind, RelationshipKind::Platonic);
+        assert_eq!(
+            relationship.members,
+            [
+                member("Natasha Romanov", Some("Marvel")),
+                member("Clint Barton", None),
+                member("Wanda", Some("Marvel/MCU")),
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_separators_make_romantic_relationship() {
+        let relationship = Relationship::parse("A & B/C").unwrap();
+        assert_eq!(relationship.kind, RelationshipKind::Romantic);
+        assert_eq!(relationship.members.len(), 3);
+    }
+
+    #[test]
+    fn single_character_is_not_a_relationship() {
+        assert_eq!(
+            Relationship::parse("Original Character (Harry Potter)"),
+            None
+        );
+        assert_eq!(Relationship::parse("Harry Potter/"), None);
+    }
+
+    #[test]
+    fn missing_stats_are_left_empty() {
+        let stats = AO3Stats::parse("");
+        assert_eq!(stats.published, None);
+        assert_eq!(stats.words, None);
+        assert_eq!(stats.chapters, None);
+        assert_eq!(stats.status, None);
+    }
+
+    #[test]
+    fn parses_series_text_of_several_series() {
+        assert_eq!(
+            SeriesMembership::parse_series_text("Part 3 of The Series, Part 1 of Other, One"),
+            [
+                SeriesMembership {
+                    name: "The Series".into(),
+                    part: Some(3)
+                },
+                SeriesMembership {
+                    name: "Other, One".into(),
+                    part: Some(1)
+                },
+            ]
+        );
+        assert_eq!(
+            SeriesMembership::parse_series_text("Unnumbered"),
+            [SeriesMembership {
+                name: "Unnumbered".into(),
+                part: None
+            }]
+        );
+        assert_eq!(SeriesMembership::parse_series_text("  "), []);
+    }
+
+    #[test]
+    fn tag_links_are_kept_by_kind() {
+        let doc = roxmltree::Document::parse(
+            r#"<dl>
+                <dd class="fandom"><a href="https://ao3/tags/hp-fandom">Harry Potter</a></dd>
+                <dd class="character"><a href="https://ao3/tags/hp">Harry Potter</a>,
+                    <a href="https://ao3/tags/rw">Ron Weasley</a></dd>
+                <dd class="series">Part 1 of <a href="https://ao3/series/1">Series</a></dd>
+            </dl>"#,
+        )
+        .unwrap();
+        let node = |class| {
+            doc.descendants()
+                .find(|elt| elt.attribute("class") == Some(class))
+                .unwrap()
+        };
+        let tags = ParsedAO3Tags::from_hash_map_of_ao3tags(&HashMap::from([
+            (AO3Tag::Fandoms, node("fandom")),
+            (AO3Tag::Characters, node("character")),
+            (AO3Tag::Series, node("series")),
+        ]));
+        assert_eq!(
+            tags.tag_url(TagKind::Fandom, "Harry Potter"),
+            Some("https://ao3/tags/hp-fandom")
+        );
+        assert_eq!(
+            tags.tag_url(TagKind::Character, "Harry Potter"),
+            Some("https://ao3/tags/hp")
+        );
+        assert_eq!(tags.tag_url(TagKind::Relationship, "Harry Potter"), None);
+        assert_eq!(
+            tags.tag_links.keys().collect::<Vec<_>>(),
+            [&TagKind::Fandom, &TagKind::Character]
+        );
+    }
+}
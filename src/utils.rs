@@ -1,3 +1,6 @@
+use std::path::{self, Path, PathBuf};
+
+use itertools::Itertools;
 use roxmltree::Node;
 
 macro_rules! mkregex {
@@ -25,6 +28,15 @@ pub fn full_node_text<'input>(node: &Node<'_, 'input>) -> &'input str {
     &node.document().input_text()[node.range()]
 }
 
+/// Text of `node` and all its descendants, with runs of whitespace collapsed.
+pub fn node_inner_text(node: &Node) -> String {
+    node.descendants()
+        .filter(|elt| elt.is_text())
+        .filter_map(|elt| elt.text())
+        .flat_map(str::split_whitespace)
+        .join(" ")
+}
+
 pub fn parse_sequence_of_node_text_children<'a>(
     node: &'a Node<'a, 'a>,
 ) -> impl Iterator<Item = &'a str> {
@@ -40,11 +52,26 @@ pub fn parse_sequence_of_node_text_children<'a>(
     .map(|s| s.text().unwrap_or("").trim())
 }
 
-pub fn vec_as_newlines<S>(v: &Vec<String>, s: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    s.serialize_str(&v.join("\n"))
+/// Text and target of every link (`<a href="...">`) inside `node`.
+pub fn node_links<'a>(node: &Node<'a, 'a>) -> impl Iterator<Item = (String, &'a str)> {
+    node.descendants()
+        .filter(|elt| elt.has_tag_name("a"))
+        .filter_map(|link| Some((node_inner_text(&link), link.attribute("href")?)))
+}
+
+/// Absolute form of `path` with symbolic links resolved, so that it can be compared with
+/// paths given in another form or from another directory. A file that no longer exists is
+/// resolved through its parent directory.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    if let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) {
+        if let Ok(parent) = parent.canonicalize() {
+            return parent.join(file_name);
+        }
+    }
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
 }
 
 // pub fn serialize_pathbuf<S>(path: &PathBuf, ser: S) -> Result<S::Ok, S::Error>
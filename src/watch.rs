@@ -0,0 +1,186 @@
+//! Keeping an exported catalog up to date while epubs are added to, changed in or removed
+//! from the input directories.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use anyhow::{bail, Result as AnyResult};
+use log::{info, warn};
+use notify::{
+    event::{EventKind, ModifyKind},
+    RecursiveMode, Watcher,
+};
+
+use crate::{
+    cache::ScanCache,
+    cli::ExportArgs,
+    export::{export_catalog, ExportFormat},
+    get_data::scan_library,
+    serialization::FullFicInfo,
+    utils::canonical_path,
+};
+
+/// How long the input directories must stay quiet before the changes are processed, so
+/// that epubs still being downloaded or copied are explored once, when complete.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Fics of the watched directories, keyed by the path of their epub.
+struct WatchedLibrary {
+    fics: BTreeMap<PathBuf, FullFicInfo>,
+    cache: Option<ScanCache>,
+}
+
+impl WatchedLibrary {
+    /// Whether a change at `path` can affect the library: an epub, or a directory that may
+    /// hold some. Other files, like the exported catalog itself, are ignored.
+    fn is_relevant(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension == "epub")
+            || path.is_dir()
+            || self.fics.keys().any(|fic_path| fic_path.starts_with(path))
+    }
+
+    /// Re-explores the epubs at or under the changed `paths`, and forgets those that no
+    /// longer exist. Returns the fics explored again.
+    fn update(&mut self, paths: &BTreeSet<PathBuf>, args: &ExportArgs) -> Vec<FullFicInfo> {
+        let mut removed = BTreeSet::new();
+        self.fics.retain(|fic_path, _| {
+            let is_changed = paths.iter().any(|path| fic_path.starts_with(path));
+            if is_changed {
+                removed.insert(fic_path.clone());
+            }
+            !is_changed
+        });
+        // Epubs inside a changed directory are explored with it, not again on their own.
+        let existing = paths.iter().filter(|path| {
+            path.exists()
+                && !paths
+                    .iter()
+                    .any(|other| other != *path && path.starts_with(other))
+        });
+        let updated = scan_library(existing, self.cache.as_mut(), args.scan.spine_search());
+        for fic_info in &updated {
+            let path = &fic_info.meta_info.path_to_file;
+            removed.remove(path);
+            self.fics.insert(path.clone(), fic_info.clone());
+        }
+        updated
+            .iter()
+            .map(|fic_info| ("updated", &fic_info.meta_info.path_to_file))
+            .chain(removed.iter().map(|path| ("removed", path)))
+            .for_each(|(change, path)| println!("{} {}", change, path.display()));
+        updated
+    }
+
+    /// Saves the cache, only warning on failure: it is an optimisation and watching goes on
+    /// without it.
+    fn save_cache(&mut self, args: &ExportArgs) {
+        if let (Some(cache), Some(cache_path)) = (&mut self.cache, &args.scan.cache) {
+            if let Err(err) = cache.save(cache_path) {
+                warn!("cannot save cache `{}`: {}", cache_path.display(), err);
+            }
+        }
+    }
+}
+
+/// Writes the catalog, leaving out the fics not matching the filter. SQLite catalogs are
+/// updated with the `updated` fics only, other formats are rewritten from the whole library.
+fn write_output(
+    library: &WatchedLibrary,
+    updated: &[FullFicInfo],
+    format: ExportFormat,
+    args: &ExportArgs,
+) -> AnyResult<()> {
+    let fics = match format {
+        ExportFormat::Sqlite => updated.to_vec(),
+        _ => library.fics.values().cloned().collect(),
+    };
+    export_catalog(
+        &args.output,
+        format,
+        &args.options,
+        args.scan.filter.as_ref(),
+        &fics,
+    )
+}
+
+/// Paths touched by `event` in canonical form, like the paths of the library, unless it only
+/// concerns access times or permissions.
+fn changed_paths(event: notify::Result<notify::Event>) -> Vec<PathBuf> {
+    match event {
+        Ok(event) => match event.kind {
+            EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => vec![],
+            _ => event
+                .paths
+                .iter()
+                .map(|path| canonical_path(path))
+                .collect(),
+        },
+        Err(err) => {
+            warn!("error while watching the input directories: {}", err);
+            vec![]
+        }
+    }
+}
+
+/// Exports the catalog like `export`, then watches the input directories and updates the
+/// catalog whenever epubs are created, modified, renamed or deleted. Runs until
+/// interrupted.
+pub fn watch_library(args: &ExportArgs) -> AnyResult<()> {
+    if args.scan.from_catalog.is_some() {
+        bail!("watching needs input directories, not a catalog");
+    }
+    let format = ExportFormat::resolve(args.format, &args.output)?;
+    let mut library = WatchedLibrary {
+        fics: BTreeMap::new(),
+        cache: args
+            .scan
+            .cache
+            .as_ref()
+            .map(|cache_path| ScanCache::load(cache_path, args.scan.hash)),
+    };
+    // The library is keyed by canonical paths, which the changes reported by the watcher are
+    // turned into as well.
+    let inputs = args
+        .scan
+        .inputs
+        .iter()
+        .map(|input| canonical_path(input))
+        .collect::<BTreeSet<_>>();
+    let updated = library.update(&inputs, args);
+    write_output(&library, &updated, format, args)?;
+    library.save_cache(args);
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for input in &inputs {
+        watcher.watch(input, RecursiveMode::Recursive)?;
+    }
+    info!(
+        "exported {} epubs to `{}`, watching for changes",
+        library.fics.len(),
+        args.output.display()
+    );
+
+    loop {
+        let mut paths = BTreeSet::new();
+        paths.extend(changed_paths(receiver.recv()?));
+        while let Ok(event) = receiver.recv_timeout(SETTLE_TIME) {
+            paths.extend(changed_paths(event));
+        }
+        paths.retain(|path| library.is_relevant(path));
+        if paths.is_empty() {
+            continue;
+        }
+
+        let updated = library.update(&paths, args);
+        if let Err(err) = write_output(&library, &updated, format, args) {
+            warn!("cannot update `{}`: {}", args.output.display(), err);
+        }
+        library.save_cache(args);
+    }
+}
@@ -0,0 +1,522 @@
+//! Writing the parsed metadata back into the OPF package document of the epubs, so that
+//! e-readers that only look at the OPF see the tags and series.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result as AnyResult};
+use itertools::Itertools;
+use log::{info, warn};
+use roxmltree::{Document, Node};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    organize::free_destination, serialization::FullFicInfo, tags::ParsedAO3Tags,
+    utils::canonical_path,
+};
+
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+const MIMETYPE_PATH: &str = "mimetype";
+
+/// Language codes for the language names used by the archives, for epubs lacking
+/// `dc:language`. Unknown names are written as they are.
+const LANGUAGE_CODES: &[(&str, &str)] = &[
+    ("english", "en"),
+    ("русский", "ru"),
+    ("russian", "ru"),
+    ("español", "es"),
+    ("spanish", "es"),
+    ("français", "fr"),
+    ("french", "fr"),
+    ("deutsch", "de"),
+    ("german", "de"),
+    ("italiano", "it"),
+    ("português", "pt"),
+    ("polski", "pl"),
+    ("中文", "zh"),
+    ("chinese", "zh"),
+    ("日本語", "ja"),
+    ("japanese", "ja"),
+    ("한국어", "ko"),
+    ("korean", "ko"),
+];
+
+/// Replacement of a byte range of the OPF source.
+struct Edit {
+    range: Range<usize>,
+    replacement: String,
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes the tags of `fic_info` into the OPF of its epub and saves the result at
+/// `destination`, which may be the epub itself. The archive is rebuilt through a temporary
+/// file, with `mimetype` first and uncompressed as the EPUB specification requires.
+///
+/// Returns whether the OPF changed; an epub rewritten in place is left untouched when it did
+/// not. With `dry_run`, nothing is written.
+pub fn write_metadata(
+    fic_info: &FullFicInfo,
+    destination: &Path,
+    dry_run: bool,
+) -> AnyResult<bool> {
+    let tags = fic_info
+        .tags
+        .as_ref()
+        .map_err(|err| anyhow!("no parsed metadata to write: {}", err))?;
+    let source = &fic_info.meta_info.path_to_file;
+    let temp_path = PathBuf::from(format!("{}.tmp", destination.display()));
+    let changed = {
+        let mut archive = ZipArchive::new(File::open(source)?)?;
+        let opf_path = find_opf_path(&mut archive)?;
+        let opf = read_entry(&mut archive, &opf_path)?;
+        let new_opf = rewrite_opf(&opf, tags)?;
+        let changed = new_opf != opf;
+        let in_place = canonical_path(source) == canonical_path(destination);
+        if dry_run || (in_place && !changed) {
+            return Ok(changed);
+        }
+        if let Err(err) = repack(&mut archive, &opf_path, &new_opf, &temp_path) {
+            fs::remove_file(&temp_path).ok();
+            return Err(err);
+        }
+        changed
+    };
+    fs::rename(&temp_path, destination)?;
+    Ok(changed)
+}
+
+/// Where the copy of `source` goes in `copy_to`: at the same place relative to the copied
+/// directory it was found in, or directly in `copy_to` when it was given on its own.
+fn copy_destination(source: &Path, inputs: &[PathBuf], copy_to: &Path) -> PathBuf {
+    let source = canonical_path(source);
+    let relative = inputs
+        .iter()
+        .map(|input| canonical_path(input))
+        .filter(|input| input.is_dir())
+        .find_map(|input| source.strip_prefix(input).ok().map(Path::to_path_buf))
+        .or_else(|| source.file_name().map(PathBuf::from))
+        .unwrap_or_default();
+    copy_to.join(relative)
+}
+
+/// Writes the metadata of every fic with parsed tags, into the epubs themselves or into
+/// copies placed in `copy_to`, keeping the layout of the `inputs` directories. Fics that
+/// fail are reported and skipped. With `dry_run`, only prints the epubs that would change.
+pub fn write_library_metadata(
+    fics: &[FullFicInfo],
+    inputs: &[PathBuf],
+    copy_to: Option<&Path>,
+    dry_run: bool,
+) -> AnyResult<()> {
+    let mut taken = HashSet::new();
+    let mut n_written = 0;
+    let mut n_unchanged = 0;
+    let mut n_failed = 0;
+    for fic_info in fics.iter().filter(|fic_info| fic_info.tags.is_ok()) {
+        let source = &fic_info.meta_info.path_to_file;
+        let destination = match copy_to {
+            Some(copy_to) => {
+                let destination = copy_destination(source, inputs, copy_to);
+                // Epubs of different inputs may have the same relative path.
+                let destination = if taken.contains(&destination) {
+                    free_destination(&destination, &taken)
+                } else {
+                    destination
+                };
+                taken.insert(destination.clone());
+                destination
+            }
+            None => source.clone(),
+        };
+        let written = (|| -> AnyResult<bool> {
+            if let (false, Some(parent)) = (dry_run, destination.parent()) {
+                fs::create_dir_all(parent)?;
+            }
+            write_metadata(fic_info, &destination, dry_run)
+        })();
+        match written {
+            Ok(false) if copy_to.is_none() => n_unchanged += 1,
+            Ok(_) => {
+                match copy_to {
+                    Some(_) => println!("{} -> {}", source.display(), destination.display()),
+                    None => println!("{}", source.display()),
+                }
+                n_written += 1;
+            }
+            Err(err) => {
+                warn!("cannot write metadata of `{}`: {}", source.display(), err);
+                n_failed += 1;
+            }
+        }
+    }
+    info!(
+        "{} metadata to {} epubs, {} already up to date, skipped {} without parsed tags",
+        if dry_run { "would write" } else { "wrote" },
+        n_written,
+        n_unchanged,
+        fics.len() - n_written - n_unchanged - n_failed
+    );
+    if n_failed > 0 {
+        bail!("cannot write metadata to {} epubs", n_failed);
+    }
+    Ok(())
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> AnyResult<String> {
+    let mut content = String::new();
+    archive.by_name(name)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+fn find_opf_path(archive: &mut ZipArchive<File>) -> AnyResult<String> {
+    let container = read_entry(archive, CONTAINER_PATH)?;
+    let doc = Document::parse(&container)?;
+    doc.descendants()
+        .find(|elt| elt.has_tag_name("rootfile"))
+        .and_then(|rootfile| rootfile.attribute("full-path"))
+        .map(String::from)
+        .ok_or_else(|| anyhow!("no rootfile in `{}`", CONTAINER_PATH))
+}
+
+fn repack(
+    archive: &mut ZipArchive<File>,
+    opf_path: &str,
+    new_opf: &str,
+    output_path: &Path,
+) -> AnyResult<()> {
+    let mut writer = ZipWriter::new(File::create(output_path)?);
+    writer.start_file(
+        MIMETYPE_PATH,
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    writer.write_all(b"application/epub+zip")?;
+
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        match file.name() {
+            MIMETYPE_PATH => (),
+            name if name == opf_path => {
+                writer.start_file(
+                    name,
+                    SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+                )?;
+                writer.write_all(new_opf.as_bytes())?;
+            }
+            _ => writer.raw_copy_file(file)?,
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn is_meta_named(node: &Node, attribute: &str, values: &[&str]) -> bool {
+    node.has_tag_name("meta")
+        && node
+            .attribute(attribute)
+            .is_some_and(|v| values.contains(&v))
+}
+
+/// Range of `node` along with the whitespace before it, so that removing it leaves no blank
+/// line.
+fn removal_range(node: &Node) -> Range<usize> {
+    let start = node
+        .prev_sibling()
+        .filter(|prev| prev.is_text() && prev.text().is_some_and(|text| text.trim().is_empty()))
+        .map_or(node.range().start, |prev| prev.range().start);
+    start..node.range().end
+}
+
+/// Adds the subjects, series and language of `tags` to the `<metadata>` of `opf`. Series
+/// information already present is replaced when `tags` has series, and kept otherwise, as
+/// not every archive records them; subjects are only added when missing.
+fn rewrite_opf(opf: &str, tags: &ParsedAO3Tags) -> AnyResult<String> {
+    let doc = Document::parse_with_options(
+        opf,
+        roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        },
+    )?;
+    let package = doc.root_element();
+    let is_epub3 = package
+        .attribute("version")
+        .is_some_and(|version| version.starts_with('3'));
+    let Some(metadata) = package.children().find(|elt| elt.has_tag_name("metadata")) else {
+        bail!("the package document has no <metadata>");
+    };
+    let dc_element = |name: &str, text: &str| match metadata.lookup_prefix(DC_NAMESPACE) {
+        Some(prefix) if !prefix.is_empty() => {
+            format!("<{0}:{1}>{2}</{0}:{1}>", prefix, name, escape_xml(text))
+        }
+        _ => format!(
+            "<dc:{0} xmlns:dc=\"{1}\">{2}</dc:{0}>",
+            name,
+            DC_NAMESPACE,
+            escape_xml(text)
+        ),
+    };
+    let is_dc = |node: &Node, name: &str| {
+        node.tag_name().namespace() == Some(DC_NAMESPACE) && node.tag_name().name() == name
+    };
+
+    let collection_ids = metadata
+        .children()
+        .filter(|elt| is_meta_named(elt, "property", &["belongs-to-collection"]))
+        .filter_map(|elt| elt.attribute("id"))
+        .map(|id| format!("#{}", id))
+        .collect::<HashSet<_>>();
+    let mut edits = metadata
+        .children()
+        .filter(|elt| {
+            !tags.series.is_empty()
+                && (is_meta_named(elt, "name", &["calibre:series", "calibre:series_index"])
+                    || is_meta_named(elt, "property", &["belongs-to-collection"])
+                    || elt
+                        .attribute("refines")
+                        .is_some_and(|refines| collection_ids.contains(refines)))
+        })
+        .map(|elt| Edit {
+            range: removal_range(&elt),
+            replacement: String::new(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut subjects = metadata
+        .children()
+        .filter(|elt| is_dc(elt, "subject"))
+        .filter_map(|elt| elt.text())
+        .map(|text| text.trim().to_lowercase())
+        .collect::<HashSet<_>>();
+    let mut added = vec![];
+    for subject in tags
+        .fandoms
+        .iter()
+        .chain(&tags.relationships)
+        .chain(&tags.characters)
+        .chain(&tags.additional_tags)
+    {
+        if subjects.insert(subject.trim().to_lowercase()) {
+            added.push(dc_element("subject", subject));
+        }
+    }
+
+    let has_language = metadata.children().any(|elt| is_dc(&elt, "language"));
+    if let (false, Some(language)) = (has_language, &tags.language) {
+        let code = LANGUAGE_CODES
+            .iter()
+            .find(|(name, _)| language.trim().to_lowercase() == *name)
+            .map_or(language.trim(), |(_, code)| code);
+        added.push(dc_element("language", code));
+    }
+
+    if let Some(series) = tags.series.first() {
+        added.push(format!(
+            "<meta name=\"calibre:series\" content=\"{}\"/>",
+            escape_xml(&series.name)
+        ));
+        if let Some(part) = series.part {
+            added.push(format!(
+                "<meta name=\"calibre:series_index\" content=\"{}\"/>",
+                part
+            ));
+        }
+    }
+    if is_epub3 {
+        for (i, series) in tags.series.iter().enumerate() {
+            let id = format!("series-collection-{}", i + 1);
+            added.push(format!(
+                "<meta property=\"belongs-to-collection\" id=\"{}\">{}</meta>",
+                id,
+                escape_xml(&series.name)
+            ));
+            added.push(format!(
+                "<meta refines=\"#{}\" property=\"collection-type\">series</meta>",
+                id
+            ));
+            if let Some(part) = series.part {
+                added.push(format!(
+                    "<meta refines=\"#{}\" property=\"group-position\">{}</meta>",
+                    id, part
+                ));
+            }
+        }
+    }
+
+    if !added.is_empty() {
+        let closing_tag = opf[..metadata.range().end]
+            .rfind("</")
+            .filter(|&i| i > metadata.range().start)
+            .ok_or_else(|| anyhow!("the package document has an empty <metadata/>"))?;
+        // The added elements follow the last child, replacing the whitespace before the
+        // closing tag.
+        let insert_at = opf[..closing_tag].trim_end().len();
+        edits.push(Edit {
+            range: insert_at..closing_tag,
+            replacement: format!(
+                "{}\n",
+                added.iter().map(|elt| format!("\n  {}", elt)).join("")
+            ),
+        });
+    }
+
+    let mut result = opf.to_string();
+    for edit in edits
+        .into_iter()
+        .sorted_by_key(|edit| edit.range.start)
+        .rev()
+    {
+        result.replace_range(edit.range, &edit.replacement);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tags::SeriesMembership;
+
+    const EPUB2_OPF: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>A Work</dc:title>
+    <dc:subject>Harry Potter - J. K. Rowling</dc:subject>
+    <meta name="calibre:series" content="Old Series"/>
+    <meta name="calibre:series_index" content="7"/>
+  </metadata>
+  <manifest/>
+</package>
+"#;
+
+    const EPUB3_OPF: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>A Work</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="belongs-to-collection" id="old">Old Series</meta>
+    <meta refines="#old" property="collection-type">series</meta>
+  </metadata>
+  <manifest/>
+</package>
+"##;
+
+    fn tags() -> ParsedAO3Tags {
+        ParsedAO3Tags {
+            fandoms: vec!["harry potter - J. K. Rowling".to_string()],
+            relationships: vec!["Harry Potter & Hermione Granger".to_string()],
+            characters: vec!["Harry Potter".to_string()],
+            language: Some("Русский".to_string()),
+            series: vec![SeriesMembership {
+                name: "The <New> Series".to_string(),
+                part: Some(2),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn metadata_texts(opf: &str, name: &str) -> Vec<String> {
+        let doc = Document::parse(opf).unwrap();
+        doc.descendants()
+            .filter(|elt| elt.has_tag_name((DC_NAMESPACE, name)))
+            .filter_map(|elt| elt.text().map(String::from))
+            .collect()
+    }
+
+    fn meta_contents(opf: &str, name: &str) -> Vec<String> {
+        let doc = Document::parse(opf).unwrap();
+        doc.descendants()
+            .filter(|elt| is_meta_named(elt, "name", &[name]))
+            .filter_map(|elt| elt.attribute("content").map(String::from))
+            .collect()
+    }
+
+    #[test]
+    fn adds_missing_subjects_once() {
+        let opf = rewrite_opf(EPUB2_OPF, &tags()).unwrap();
+        assert_eq!(
+            metadata_texts(&opf, "subject"),
+            [
+                "Harry Potter - J. K. Rowling",
+                "Harry Potter & Hermione Granger",
+                "Harry Potter",
+            ]
+        );
+    }
+
+    #[test]
+    fn replaces_calibre_series() {
+        let opf = rewrite_opf(EPUB2_OPF, &tags()).unwrap();
+        assert_eq!(meta_contents(&opf, "calibre:series"), ["The <New> Series"]);
+        assert_eq!(meta_contents(&opf, "calibre:series_index"), ["2"]);
+        assert!(!opf.contains("belongs-to-collection"));
+    }
+
+    #[test]
+    fn adds_language_code_when_missing() {
+        let opf = rewrite_opf(EPUB2_OPF, &tags()).unwrap();
+        assert_eq!(metadata_texts(&opf, "language"), ["ru"]);
+
+        let opf = rewrite_opf(EPUB3_OPF, &tags()).unwrap();
+        assert_eq!(metadata_texts(&opf, "language"), ["en"]);
+    }
+
+    #[test]
+    fn replaces_epub3_collections() {
+        let opf = rewrite_opf(EPUB3_OPF, &tags()).unwrap();
+        let doc = Document::parse(&opf).unwrap();
+        let metas = doc
+            .descendants()
+            .filter(|elt| elt.has_tag_name("meta") && elt.attribute("property").is_some())
+            .map(|elt| {
+                (
+                    elt.attribute("property").unwrap(),
+                    elt.attribute("refines"),
+                    elt.text().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            metas,
+            [
+                ("belongs-to-collection", None, "The <New> Series"),
+                ("collection-type", Some("#series-collection-1"), "series"),
+                ("group-position", Some("#series-collection-1"), "2"),
+            ]
+        );
+        assert_eq!(meta_contents(&opf, "calibre:series"), ["The <New> Series"]);
+    }
+
+    #[test]
+    fn rewriting_twice_changes_nothing() {
+        for original in [EPUB2_OPF, EPUB3_OPF] {
+            let once = rewrite_opf(original, &tags()).unwrap();
+            assert_eq!(rewrite_opf(&once, &tags()).unwrap(), once);
+        }
+    }
+
+    #[test]
+    fn keeps_existing_series_when_none_was_parsed() {
+        let opf = rewrite_opf(
+            EPUB3_OPF,
+            &ParsedAO3Tags {
+                language: Some("English".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(opf, EPUB3_OPF);
+        let opf = rewrite_opf(EPUB2_OPF, &ParsedAO3Tags::default()).unwrap();
+        assert_eq!(opf, EPUB2_OPF);
+    }
+}